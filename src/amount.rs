@@ -0,0 +1,210 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+const DECIMALS: u32 = 4;
+const SCALE: i64 = 10i64.pow(DECIMALS);
+
+#[derive(Error, PartialEq, Debug)]
+pub enum AmountError {
+    #[error("Amount is empty")]
+    Empty,
+    #[error("Amount is not a valid decimal number")]
+    Invalid,
+    #[error("Amount has more than four decimal places")]
+    TooPrecise,
+    #[error("Amount is out of range")]
+    OutOfRange,
+}
+
+/// Monetary amount, stored as a fixed-point number of ten-thousandths.
+#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Builds an amount from a raw count of ten-thousandths (e.g. `15000` is `1.5`).
+    pub const fn from_units(units: i64) -> Self {
+        Self(units)
+    }
+
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn checked_neg(self) -> Option<Amount> {
+        self.0.checked_neg().map(Amount)
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_abs(self) -> Amount {
+        Amount(self.0.saturating_abs())
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.as_bytes().first() {
+            None => return Err(AmountError::Empty),
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            Some(_) => (false, s),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            None => (digits, ""),
+            Some(parts) => parts,
+        };
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(AmountError::Invalid);
+        }
+        if !whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(AmountError::Invalid);
+        }
+        if fraction.len() > DECIMALS as usize {
+            return Err(AmountError::TooPrecise);
+        }
+
+        let mut units: i64 = 0;
+        for b in whole.bytes() {
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(i64::from(b - b'0')))
+                .ok_or(AmountError::OutOfRange)?;
+        }
+        let mut fraction_units: i64 = 0;
+        for b in fraction.bytes() {
+            fraction_units = fraction_units * 10 + i64::from(b - b'0');
+        }
+        fraction_units *= 10i64.pow(DECIMALS - fraction.len() as u32);
+        units = units
+            .checked_mul(SCALE)
+            .and_then(|u| u.checked_add(fraction_units))
+            .ok_or(AmountError::OutOfRange)?;
+
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            abs / scale,
+            abs % scale,
+            width = DECIMALS as usize
+        )
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal amount with at most four decimal places")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_exact_decimals() {
+        assert_eq!("1".parse(), Ok(Amount::from_units(10_000)));
+        assert_eq!("1.5".parse(), Ok(Amount::from_units(15_000)));
+        assert_eq!("0.0001".parse(), Ok(Amount::from_units(1)));
+        assert_eq!("-2.25".parse(), Ok(Amount::from_units(-22_500)));
+        assert_eq!(".5".parse(), Ok(Amount::from_units(5_000)));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        assert_eq!("".parse::<Amount>(), Err(AmountError::Empty));
+        assert_eq!(".".parse::<Amount>(), Err(AmountError::Invalid));
+        assert_eq!("1.2.3".parse::<Amount>(), Err(AmountError::Invalid));
+        assert_eq!("1e5".parse::<Amount>(), Err(AmountError::Invalid));
+        assert_eq!("1.00001".parse::<Amount>(), Err(AmountError::TooPrecise));
+        assert_eq!(
+            "99999999999999999999".parse::<Amount>(),
+            Err(AmountError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn formats_with_four_decimals() {
+        assert_eq!(Amount::from_units(15_000).to_string(), "1.5000");
+        assert_eq!(Amount::from_units(1).to_string(), "0.0001");
+        assert_eq!(Amount::from_units(-22_500).to_string(), "-2.2500");
+        assert_eq!(Amount::ZERO.to_string(), "0.0000");
+    }
+
+    #[test]
+    fn checked_arithmetic_detects_overflow() {
+        let max = Amount::from_units(i64::MAX);
+        assert_eq!(max.checked_add(Amount::from_units(1)), None);
+        assert_eq!(
+            Amount::from_units(1).checked_sub(Amount::from_units(3)),
+            Some(Amount::from_units(-2))
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum CurrencyError {
+    #[error("Currency {0:?} is not a three-letter code")]
+    Invalid(String),
+}
+
+/// Three-letter currency code, e.g. `EUR`, always stored in upper case.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// ISO 4217's code for "no currency", given to events which do not specify one.
+    pub const UNSPECIFIED: Currency = Currency(*b"XXX");
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are ever stored
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::UNSPECIFIED
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b, c] if s.bytes().all(|b| b.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(CurrencyError::Invalid(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Currency({})", self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+struct CurrencyVisitor;
+
+impl<'de> Visitor<'de> for CurrencyVisitor {
+    type Value = Currency;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a three-letter currency code")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_three_letter_codes() {
+        assert_eq!("EUR".parse::<Currency>().unwrap().to_string(), "EUR");
+        assert_eq!("usd".parse::<Currency>().unwrap().to_string(), "USD");
+        assert_eq!(Currency::default().to_string(), "XXX");
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        for code in ["", "EU", "EURO", "E1R", "€UR"] {
+            assert_eq!(
+                code.parse::<Currency>(),
+                Err(CurrencyError::Invalid(code.to_owned()))
+            );
+        }
+    }
+}
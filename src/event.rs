@@ -0,0 +1,608 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::amount::Amount;
+use crate::currency::Currency;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum EventError {
+    #[error("A {0} event must specify an amount")]
+    MissingAmount(EventType),
+    #[error("A {0} event must not specify an amount")]
+    UnexpectedAmount(EventType),
+    #[error("A {0} event must specify a positive amount")]
+    NonPositiveAmount(EventType),
+    #[error("Unknown event type {0:?}")]
+    UnknownType(String),
+    #[error("An adjustment event must specify a reason")]
+    MissingReason,
+    #[error("A {0} event must not specify a reason")]
+    UnexpectedReason(EventType),
+    #[error("A {0} event must not specify a currency")]
+    UnexpectedCurrency(EventType),
+    #[error("A convert event must specify a target currency")]
+    MissingTargetCurrency,
+    #[error("A {0} event must not specify a target currency")]
+    UnexpectedTargetCurrency(EventType),
+    #[error("A convert event must specify a target currency other than its currency")]
+    SameTargetCurrency,
+    #[error("A transfer event must specify a destination client")]
+    MissingDestination,
+    #[error("A {0} event must not specify a destination client")]
+    UnexpectedDestination(EventType),
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Lock,
+    Unlock,
+    Adjustment,
+    Convert,
+    Transfer,
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EventType::Deposit => "deposit",
+            EventType::Withdrawal => "withdrawal",
+            EventType::Dispute => "dispute",
+            EventType::Resolve => "resolve",
+            EventType::Chargeback => "chargeback",
+            EventType::Lock => "lock",
+            EventType::Unlock => "unlock",
+            EventType::Adjustment => "adjustment",
+            EventType::Convert => "convert",
+            EventType::Transfer => "transfer",
+        })
+    }
+}
+
+impl FromStr for EventType {
+    type Err = EventError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "deposit" => EventType::Deposit,
+            "withdrawal" => EventType::Withdrawal,
+            "dispute" => EventType::Dispute,
+            "resolve" => EventType::Resolve,
+            "chargeback" => EventType::Chargeback,
+            "lock" => EventType::Lock,
+            "unlock" => EventType::Unlock,
+            "adjustment" => EventType::Adjustment,
+            "convert" => EventType::Convert,
+            "transfer" => EventType::Transfer,
+            _ => return Err(EventError::UnknownType(s.to_owned())),
+        })
+    }
+}
+
+/// Time an event occurred, in seconds since the Unix epoch.
+pub type Timestamp = u64;
+
+/// Data for events which move funds in or out of a client account.
+#[derive(PartialEq, Debug, Clone)]
+pub struct FundsEvent {
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Amount,
+    pub currency: Currency,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Data for events which refer back to a previously processed transaction.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ReferenceEvent {
+    pub client: u16,
+    pub tx: u32,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Data for administrative events which change the state of a client account.
+///
+/// `tx` identifies the event itself in the transaction ledger, so it must be unique.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AccountEvent {
+    pub client: u16,
+    pub tx: u32,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Data for a manual correction of a client's available balance.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AdjustmentEvent {
+    pub client: u16,
+    pub tx: u32,
+    /// Credited to the client if positive, debited if negative.
+    pub amount: Amount,
+    pub currency: Currency,
+    /// Why the adjustment was made, e.g. `fee_refund`.
+    pub reason: String,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Data for an exchange of funds between two of a client's currencies.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConvertEvent {
+    pub client: u16,
+    pub tx: u32,
+    /// Taken from the client's available balance in `currency`.
+    pub amount: Amount,
+    pub currency: Currency,
+    /// Currency credited with `amount` at the exchange rate.
+    pub target: Currency,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Data for a movement of funds from one client's account to another's.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TransferEvent {
+    /// The source client, whose available balance is debited.
+    pub client: u16,
+    pub tx: u32,
+    /// The client credited with `amount`.
+    pub destination: u16,
+    pub amount: Amount,
+    pub currency: Currency,
+    pub timestamp: Option<Timestamp>,
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "EventRecord")]
+pub enum Event {
+    Deposit(FundsEvent),
+    Withdrawal(FundsEvent),
+    Dispute(ReferenceEvent),
+    Resolve(ReferenceEvent),
+    Chargeback(ReferenceEvent),
+    Lock(AccountEvent),
+    Unlock(AccountEvent),
+    Adjustment(AdjustmentEvent),
+    Convert(ConvertEvent),
+    Transfer(TransferEvent),
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::Deposit(_) => EventType::Deposit,
+            Event::Withdrawal(_) => EventType::Withdrawal,
+            Event::Dispute(_) => EventType::Dispute,
+            Event::Resolve(_) => EventType::Resolve,
+            Event::Chargeback(_) => EventType::Chargeback,
+            Event::Lock(_) => EventType::Lock,
+            Event::Unlock(_) => EventType::Unlock,
+            Event::Adjustment(_) => EventType::Adjustment,
+            Event::Convert(_) => EventType::Convert,
+            Event::Transfer(_) => EventType::Transfer,
+        }
+    }
+
+    pub fn client(&self) -> u16 {
+        match self {
+            Event::Deposit(e) | Event::Withdrawal(e) => e.client,
+            Event::Dispute(e) | Event::Resolve(e) | Event::Chargeback(e) => e.client,
+            Event::Lock(e) | Event::Unlock(e) => e.client,
+            Event::Adjustment(e) => e.client,
+            Event::Convert(e) => e.client,
+            Event::Transfer(e) => e.client,
+        }
+    }
+
+    pub fn tx(&self) -> u32 {
+        match self {
+            Event::Deposit(e) | Event::Withdrawal(e) => e.tx,
+            Event::Dispute(e) | Event::Resolve(e) | Event::Chargeback(e) => e.tx,
+            Event::Lock(e) | Event::Unlock(e) => e.tx,
+            Event::Adjustment(e) => e.tx,
+            Event::Convert(e) => e.tx,
+            Event::Transfer(e) => e.tx,
+        }
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        match self {
+            Event::Deposit(e) | Event::Withdrawal(e) => e.timestamp,
+            Event::Dispute(e) | Event::Resolve(e) | Event::Chargeback(e) => e.timestamp,
+            Event::Lock(e) | Event::Unlock(e) => e.timestamp,
+            Event::Adjustment(e) => e.timestamp,
+            Event::Convert(e) => e.timestamp,
+            Event::Transfer(e) => e.timestamp,
+        }
+    }
+}
+
+/// Flat shape of an input row, validated into an `Event` upon deserialization.
+///
+/// Every column after `amount` is optional, so inputs without it remain valid.
+#[derive(Deserialize, Debug)]
+struct EventRecord {
+    #[serde(rename = "type")]
+    event_type: EventType,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+    /// Currency of the funds moved, `XXX` if blank.
+    #[serde(default)]
+    currency: Option<Currency>,
+    /// Currency a `convert` event credits.
+    #[serde(default)]
+    target_currency: Option<Currency>,
+    /// Client a `transfer` event credits.
+    #[serde(default)]
+    destination: Option<u16>,
+    /// Why an `adjustment` was made.
+    #[serde(default)]
+    reason: Option<String>,
+    /// When the event occurred, in seconds since the Unix epoch.
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+impl TryFrom<EventRecord> for Event {
+    type Error = EventError;
+
+    fn try_from(record: EventRecord) -> Result<Self, Self::Error> {
+        let EventRecord {
+            event_type,
+            client,
+            tx,
+            amount,
+            currency,
+            target_currency,
+            destination,
+            reason,
+            timestamp,
+        } = record;
+        // Only adjustments carry a reason
+        if reason.is_some() && event_type != EventType::Adjustment {
+            return Err(EventError::UnexpectedReason(event_type));
+        }
+        // Disputes act in the currency of the disputed transaction, and account events on every
+        // currency at once, so only events which move funds name one
+        let moves_funds = matches!(
+            event_type,
+            EventType::Deposit
+                | EventType::Withdrawal
+                | EventType::Adjustment
+                | EventType::Convert
+                | EventType::Transfer
+        );
+        if currency.is_some() && !moves_funds {
+            return Err(EventError::UnexpectedCurrency(event_type));
+        }
+        if target_currency.is_some() && event_type != EventType::Convert {
+            return Err(EventError::UnexpectedTargetCurrency(event_type));
+        }
+        if destination.is_some() && event_type != EventType::Transfer {
+            return Err(EventError::UnexpectedDestination(event_type));
+        }
+        let currency = currency.unwrap_or_default();
+        // Only adjustments are signed; every other amount moves funds in one direction
+        let positive = |amount: Option<Amount>| match amount {
+            None => Err(EventError::MissingAmount(event_type)),
+            Some(amount) if !amount.is_positive() => Err(EventError::NonPositiveAmount(event_type)),
+            Some(amount) => Ok(amount),
+        };
+        let funds = |amount: Option<Amount>| {
+            Ok(FundsEvent {
+                client,
+                tx,
+                amount: positive(amount)?,
+                currency,
+                timestamp,
+            })
+        };
+        let reference = |amount: Option<Amount>| match amount {
+            Some(_) => Err(EventError::UnexpectedAmount(event_type)),
+            None => Ok(ReferenceEvent {
+                client,
+                tx,
+                timestamp,
+            }),
+        };
+        let account = |amount: Option<Amount>| match amount {
+            Some(_) => Err(EventError::UnexpectedAmount(event_type)),
+            None => Ok(AccountEvent {
+                client,
+                tx,
+                timestamp,
+            }),
+        };
+        Ok(match event_type {
+            EventType::Deposit => Event::Deposit(funds(amount)?),
+            EventType::Withdrawal => Event::Withdrawal(funds(amount)?),
+            EventType::Dispute => Event::Dispute(reference(amount)?),
+            EventType::Resolve => Event::Resolve(reference(amount)?),
+            EventType::Chargeback => Event::Chargeback(reference(amount)?),
+            EventType::Lock => Event::Lock(account(amount)?),
+            EventType::Unlock => Event::Unlock(account(amount)?),
+            EventType::Adjustment => Event::Adjustment(AdjustmentEvent {
+                client,
+                tx,
+                amount: amount.ok_or(EventError::MissingAmount(event_type))?,
+                currency,
+                reason: reason.ok_or(EventError::MissingReason)?,
+                timestamp,
+            }),
+            EventType::Convert => Event::Convert(ConvertEvent {
+                client,
+                tx,
+                amount: positive(amount)?,
+                currency,
+                target: match target_currency {
+                    None => return Err(EventError::MissingTargetCurrency),
+                    Some(target) if target == currency => {
+                        return Err(EventError::SameTargetCurrency)
+                    }
+                    Some(target) => target,
+                },
+                timestamp,
+            }),
+            EventType::Transfer => Event::Transfer(TransferEvent {
+                client,
+                tx,
+                destination: destination.ok_or(EventError::MissingDestination)?,
+                amount: positive(amount)?,
+                currency,
+                timestamp,
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<Result<Event, csv::Error>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .deserialize::<Event>()
+            .collect()
+    }
+
+    #[test]
+    fn parses_each_event_type() {
+        let events = parse(
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.5\n\
+             withdrawal, 1, 2, 0.5\n\
+             dispute, 1, 1,\n\
+             resolve, 1, 1,\n\
+             chargeback, 1, 1,\n",
+        );
+        let events: Vec<Event> = events.into_iter().map(Result::unwrap).collect();
+        let amount = |s: &str| s.parse::<Amount>().unwrap();
+        let reference = ReferenceEvent {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        assert_eq!(
+            events,
+            vec![
+                Event::Deposit(FundsEvent {
+                    client: 1,
+                    tx: 1,
+                    amount: amount("1.5"),
+                    currency: Currency::UNSPECIFIED,
+                    timestamp: None,
+                }),
+                Event::Withdrawal(FundsEvent {
+                    client: 1,
+                    tx: 2,
+                    amount: amount("0.5"),
+                    currency: Currency::UNSPECIFIED,
+                    timestamp: None,
+                }),
+                Event::Dispute(reference.clone()),
+                Event::Resolve(reference.clone()),
+                Event::Chargeback(reference),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_deposit_without_amount() {
+        let events = parse("type, client, tx, amount\ndeposit, 1, 1,\n");
+        let message = events[0].as_ref().unwrap_err().to_string();
+        assert!(message.contains("line: 2"), "{}", message);
+        assert!(message.contains(&EventError::MissingAmount(EventType::Deposit).to_string()));
+    }
+
+    #[test]
+    fn rejects_dispute_with_amount() {
+        let events = parse("type, client, tx, amount\ndeposit, 1, 1, 1.0\ndispute, 1, 1, 1.0\n");
+        assert!(events[0].is_ok());
+        let message = events[1].as_ref().unwrap_err().to_string();
+        assert!(message.contains("line: 3"), "{}", message);
+        assert!(message.contains(&EventError::UnexpectedAmount(EventType::Dispute).to_string()));
+    }
+
+    #[test]
+    fn rejects_deposits_and_withdrawals_without_positive_amount() {
+        let events = parse(
+            "type, client, tx, amount\n\
+             withdrawal, 1, 1, -5\n\
+             deposit, 1, 2, 0\n\
+             deposit, 1, 3, +5\n",
+        );
+        for (event, event_type) in events[..2]
+            .iter()
+            .zip(&[EventType::Withdrawal, EventType::Deposit])
+        {
+            let message = event.as_ref().unwrap_err().to_string();
+            assert!(
+                message.contains(&EventError::NonPositiveAmount(*event_type).to_string()),
+                "{}",
+                message
+            );
+        }
+        match &events[2] {
+            Ok(Event::Deposit(event)) => assert_eq!(event.amount, Amount::from_units(50_000)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_optional_timestamp() {
+        let events = parse(
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 1.0, 1700000000\n\
+             dispute, 1, 1, ,\n",
+        );
+        let events: Vec<Event> = events.into_iter().map(Result::unwrap).collect();
+        assert_eq!(events[0].timestamp(), Some(1_700_000_000));
+        assert_eq!(events[1].timestamp(), None);
+    }
+
+    #[test]
+    fn parses_administrative_events() {
+        let events = parse(
+            "type, client, tx, amount, reason\n\
+             lock, 1, 7, ,\n\
+             unlock, 1, 8, ,\n\
+             adjustment, 1, 9, -2.5, fee_refund\n",
+        );
+        let events: Vec<Event> = events.into_iter().map(Result::unwrap).collect();
+        let account = |tx| AccountEvent {
+            client: 1,
+            tx,
+            timestamp: None,
+        };
+        assert_eq!(
+            events,
+            vec![
+                Event::Lock(account(7)),
+                Event::Unlock(account(8)),
+                Event::Adjustment(AdjustmentEvent {
+                    client: 1,
+                    tx: 9,
+                    amount: "-2.5".parse().unwrap(),
+                    currency: Currency::UNSPECIFIED,
+                    reason: "fee_refund".to_owned(),
+                    timestamp: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_misplaced_reasons() {
+        let events = parse(
+            "type, client, tx, amount, reason\n\
+             adjustment, 1, 1, 1.0,\n\
+             deposit, 1, 2, 1.0, fee_refund\n",
+        );
+        let message = events[0].as_ref().unwrap_err().to_string();
+        assert!(message.contains(&EventError::MissingReason.to_string()));
+        let message = events[1].as_ref().unwrap_err().to_string();
+        assert!(message.contains(&EventError::UnexpectedReason(EventType::Deposit).to_string()));
+    }
+
+    #[test]
+    fn parses_optional_currency() {
+        let events = parse(
+            "type, client, tx, amount, currency\n\
+             deposit, 1, 1, 1.0, eur\n\
+             withdrawal, 1, 2, 1.0,\n\
+             dispute, 1, 1, , EUR\n",
+        );
+        let currency = |event: &Result<Event, csv::Error>| match event {
+            Ok(Event::Deposit(e)) | Ok(Event::Withdrawal(e)) => e.currency,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(currency(&events[0]), "EUR".parse().unwrap());
+        assert_eq!(currency(&events[1]), Currency::UNSPECIFIED);
+        let message = events[2].as_ref().unwrap_err().to_string();
+        assert!(message.contains(&EventError::UnexpectedCurrency(EventType::Dispute).to_string()));
+    }
+
+    #[test]
+    fn parses_convert_events() {
+        let events = parse(
+            "type, client, tx, amount, currency, target_currency\n\
+             convert, 1, 3, 2.5, EUR, USD\n\
+             convert, 1, 4, 2.5, EUR,\n\
+             deposit, 1, 5, 2.5, EUR, USD\n\
+             convert, 1, 6, -2.5, EUR, USD\n\
+             convert, 1, 7, 0, EUR, USD\n\
+             convert, 1, 8, 2.5, EUR, EUR\n\
+             convert, 1, 9, 2.5, , XXX\n",
+        );
+        assert_eq!(
+            events[0].as_ref().unwrap(),
+            &Event::Convert(ConvertEvent {
+                client: 1,
+                tx: 3,
+                amount: "2.5".parse().unwrap(),
+                currency: "EUR".parse().unwrap(),
+                target: "USD".parse().unwrap(),
+                timestamp: None,
+            })
+        );
+        let message = events[1].as_ref().unwrap_err().to_string();
+        assert!(message.contains(&EventError::MissingTargetCurrency.to_string()));
+        let message = events[2].as_ref().unwrap_err().to_string();
+        assert!(
+            message.contains(&EventError::UnexpectedTargetCurrency(EventType::Deposit).to_string())
+        );
+        for event in &events[3..5] {
+            let message = event.as_ref().unwrap_err().to_string();
+            assert!(
+                message.contains(&EventError::NonPositiveAmount(EventType::Convert).to_string())
+            );
+        }
+        for event in &events[5..] {
+            let message = event.as_ref().unwrap_err().to_string();
+            assert!(message.contains(&EventError::SameTargetCurrency.to_string()));
+        }
+    }
+
+    #[test]
+    fn parses_transfer_events() {
+        let events = parse(
+            "type, client, tx, amount, destination\n\
+             transfer, 1, 3, 2.5, 2\n\
+             transfer, 1, 4, 2.5,\n\
+             withdrawal, 1, 5, 2.5, 2\n\
+             transfer, 1, 6, -50, 2\n\
+             transfer, 1, 7, 0, 2\n",
+        );
+        assert_eq!(
+            events[0].as_ref().unwrap(),
+            &Event::Transfer(TransferEvent {
+                client: 1,
+                tx: 3,
+                destination: 2,
+                amount: "2.5".parse().unwrap(),
+                currency: Currency::UNSPECIFIED,
+                timestamp: None,
+            })
+        );
+        let message = events[1].as_ref().unwrap_err().to_string();
+        assert!(message.contains(&EventError::MissingDestination.to_string()));
+        let message = events[2].as_ref().unwrap_err().to_string();
+        assert!(
+            message.contains(&EventError::UnexpectedDestination(EventType::Withdrawal).to_string())
+        );
+        for event in &events[3..] {
+            let message = event.as_ref().unwrap_err().to_string();
+            assert!(
+                message.contains(&EventError::NonPositiveAmount(EventType::Transfer).to_string())
+            );
+        }
+    }
+}
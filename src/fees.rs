@@ -0,0 +1,253 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::amount::Amount;
+use crate::event::EventType;
+use crate::rates::{Rate, RoundingMode};
+
+#[derive(Error, Debug)]
+pub enum FeesError {
+    #[error("Fee schedule could not be accessed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Fee schedule is malformed: {0}")]
+    Malformed(#[from] csv::Error),
+    #[error("Fee schedule charges {0} events, but only deposits and withdrawals can be charged")]
+    UnchargedType(EventType),
+    #[error("Fee schedule has a negative {event_type} tier{}", client_suffix(*.client))]
+    NegativeTier {
+        event_type: EventType,
+        client: Option<u16>,
+    },
+    #[error("Fee schedule has two {event_type} tiers from {from}{}", client_suffix(*.client))]
+    DuplicateTier {
+        event_type: EventType,
+        client: Option<u16>,
+        from: Amount,
+    },
+}
+
+fn client_suffix(client: Option<u16>) -> String {
+    client.map_or_else(String::new, |client| format!(" for client {}", client))
+}
+
+/// The fee on amounts from `from` up to the `from` of the next tier: a flat fee plus a
+/// proportion of the whole amount.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FeeTier {
+    pub from: Amount,
+    pub flat: Amount,
+    /// Proportion of the amount charged, e.g. `0.015` for 1.5%.
+    pub rate: Option<Rate>,
+}
+
+impl FeeTier {
+    /// The fee on `amount`, or `None` if it overflows an `Amount`.
+    fn fee(&self, amount: Amount, rounding: RoundingMode) -> Option<Amount> {
+        let proportional = match self.rate {
+            Some(rate) => rate.convert(amount, rounding)?,
+            None => Amount::ZERO,
+        };
+        self.flat.checked_add(proportional)
+    }
+}
+
+/// Adds `tier` to `tiers`, kept ordered by `from`, returning `false` if one with the same
+/// `from` was replaced.
+fn insert_tier(tiers: &mut Vec<FeeTier>, tier: FeeTier) -> bool {
+    match tiers.binary_search_by_key(&tier.from, |tier| tier.from) {
+        Ok(index) => {
+            tiers[index] = tier;
+            false
+        }
+        Err(index) => {
+            tiers.insert(index, tier);
+            true
+        }
+    }
+}
+
+/// A row of the fee schedule file.
+#[derive(Deserialize)]
+struct FeeRecord {
+    #[serde(rename = "type")]
+    event_type: EventType,
+    client: Option<u16>,
+    from: Option<Amount>,
+    flat: Option<Amount>,
+    rate: Option<Rate>,
+}
+
+/// Fees charged on deposits and withdrawals, in tiers by amount.
+///
+/// A client with tiers of its own for an event type is charged by those alone, instead of by
+/// the default tiers. Amounts below the lowest tier are not charged.
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct FeeSchedule {
+    tiers: HashMap<EventType, Vec<FeeTier>>,
+    overrides: HashMap<(u16, EventType), Vec<FeeTier>>,
+}
+
+impl FeeSchedule {
+    pub fn with_tier(mut self, event_type: EventType, tier: FeeTier) -> Self {
+        insert_tier(self.tiers.entry(event_type).or_default(), tier);
+        self
+    }
+
+    pub fn with_client_tier(mut self, client: u16, event_type: EventType, tier: FeeTier) -> Self {
+        insert_tier(
+            self.overrides.entry((client, event_type)).or_default(),
+            tier,
+        );
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty() && self.overrides.is_empty()
+    }
+
+    /// The fee charged to `client` on an event of `event_type` for `amount`, or `None` if it
+    /// overflows an `Amount`.
+    pub fn fee(
+        &self,
+        client: u16,
+        event_type: EventType,
+        amount: Amount,
+        rounding: RoundingMode,
+    ) -> Option<Amount> {
+        let tiers = self
+            .overrides
+            .get(&(client, event_type))
+            .or_else(|| self.tiers.get(&event_type));
+        match tiers.and_then(|tiers| tiers.iter().rev().find(|tier| tier.from <= amount)) {
+            Some(tier) => tier.fee(amount, rounding),
+            None => Some(Amount::ZERO),
+        }
+    }
+
+    /// Reads a CSV schedule with `type`, `client`, `from`, `flat` and `rate` columns, e.g.
+    /// `withdrawal,,100,0.5,0.01`; blank columns apply to every client, from zero, with no fee.
+    pub fn read<R: Read>(input: R) -> Result<Self, FeesError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        let mut schedule = FeeSchedule::default();
+        for record in reader.deserialize() {
+            let FeeRecord {
+                event_type,
+                client,
+                from,
+                flat,
+                rate,
+            } = record?;
+            if !matches!(event_type, EventType::Deposit | EventType::Withdrawal) {
+                return Err(FeesError::UnchargedType(event_type));
+            }
+            let tier = FeeTier {
+                from: from.unwrap_or_default(),
+                flat: flat.unwrap_or_default(),
+                rate,
+            };
+            if tier.from.is_negative() || tier.flat.is_negative() {
+                return Err(FeesError::NegativeTier { event_type, client });
+            }
+            let tiers = match client {
+                Some(client) => schedule.overrides.entry((client, event_type)).or_default(),
+                None => schedule.tiers.entry(event_type).or_default(),
+            };
+            if !insert_tier(tiers, tier) {
+                return Err(FeesError::DuplicateTier {
+                    event_type,
+                    client,
+                    from: tier.from,
+                });
+            }
+        }
+        Ok(schedule)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, FeesError> {
+        Self::read(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    fn fee(schedule: &FeeSchedule, client: u16, event_type: EventType, value: &str) -> Amount {
+        schedule
+            .fee(client, event_type, amount(value), RoundingMode::HalfEven)
+            .unwrap()
+    }
+
+    #[test]
+    fn charges_tier_for_amount_with_client_overrides() {
+        let schedule = FeeSchedule::read(
+            &b"type, client, from, flat, rate\n\
+               withdrawal, , , 0.5,\n\
+               withdrawal, , 100, 1, 0.01\n\
+               withdrawal, 7, , , 0.001\n\
+               deposit, 7, 10, 0.25,\n"[..],
+        )
+        .unwrap();
+        assert_eq!(
+            fee(&schedule, 1, EventType::Withdrawal, "99"),
+            amount("0.5")
+        );
+        assert_eq!(fee(&schedule, 1, EventType::Withdrawal, "200"), amount("3"));
+        assert_eq!(
+            fee(&schedule, 7, EventType::Withdrawal, "200"),
+            amount("0.2")
+        );
+        assert_eq!(fee(&schedule, 1, EventType::Deposit, "200"), Amount::ZERO);
+        assert_eq!(fee(&schedule, 7, EventType::Deposit, "9"), Amount::ZERO);
+        assert_eq!(fee(&schedule, 7, EventType::Deposit, "10"), amount("0.25"));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        assert!(matches!(
+            FeeSchedule::read(&b"type,client,from,flat,rate\ndispute,,,1,\n"[..]),
+            Err(FeesError::UnchargedType(EventType::Dispute))
+        ));
+        assert!(matches!(
+            FeeSchedule::read(
+                &b"type,client,from,flat,rate\ndeposit,3,5,1,\ndeposit,3,5.0,2,\n"[..]
+            ),
+            Err(FeesError::DuplicateTier {
+                client: Some(3),
+                ..
+            })
+        ));
+        assert!(matches!(
+            FeeSchedule::read(&b"type,client,from,flat,rate\ndeposit,,,x,\n"[..]),
+            Err(FeesError::Malformed(_))
+        ));
+        assert!(matches!(
+            FeeSchedule::read(&b"type,client,from,flat,rate\ndeposit,,,-1,\n"[..]),
+            Err(FeesError::NegativeTier {
+                event_type: EventType::Deposit,
+                client: None,
+            })
+        ));
+        assert!(matches!(
+            FeeSchedule::read(&b"type,client,from,flat,rate\nwithdrawal,4,-10,1,\n"[..]),
+            Err(FeesError::NegativeTier {
+                client: Some(4),
+                ..
+            })
+        ));
+        assert!(matches!(
+            FeeSchedule::read(&b"type,client,from,flat,rate\nwithdrawal,,,,-0.01\n"[..]),
+            Err(FeesError::Malformed(_))
+        ));
+    }
+}
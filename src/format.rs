@@ -0,0 +1,151 @@
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Encoding of the events read from an input.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum InputFormat {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line, with the same fields as the CSV columns.
+    JsonLines,
+}
+
+impl InputFormat {
+    /// The format implied by a file's extension, if it has a known one.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(InputFormat::Csv),
+            "jsonl" | "ndjson" => Some(InputFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Encoding of the client report and transaction ledger.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum OutputFormat {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// A single JSON array, one object per line.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl OutputFormat {
+    /// The format implied by a file's extension, if it has a known one.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(OutputFormat::Csv),
+            "json" => Some(OutputFormat::Json),
+            "jsonl" | "ndjson" => Some(OutputFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a sequence of records to `output` in an `OutputFormat`.
+///
+/// Records are written as they are encoded; `finish` must be called to complete the output.
+pub struct Encoder<W: Write> {
+    inner: Inner<W>,
+}
+
+enum Inner<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json { output: W, records: usize },
+    JsonLines(W),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(format: OutputFormat, output: W) -> Self {
+        let inner = match format {
+            OutputFormat::Csv => Inner::Csv(Box::new(csv::Writer::from_writer(output))),
+            OutputFormat::Json => Inner::Json { output, records: 0 },
+            OutputFormat::JsonLines => Inner::JsonLines(output),
+        };
+        Self { inner }
+    }
+
+    pub fn encode<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Csv(writer) => writer.serialize(record)?,
+            Inner::Json { output, records } => {
+                output.write_all(if *records == 0 { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *output, record)?;
+                *records += 1;
+            }
+            Inner::JsonLines(output) => {
+                serde_json::to_writer(&mut *output, record)?;
+                output.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the output, e.g. closing a JSON array, and flushes it.
+    pub fn finish(self) -> io::Result<()> {
+        match self.inner {
+            Inner::Csv(mut writer) => writer.flush(),
+            Inner::Json {
+                mut output,
+                records,
+            } => {
+                output.write_all(if records == 0 { b"[]\n" } else { b"\n]\n" })?;
+                output.flush()
+            }
+            Inner::JsonLines(mut output) => output.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Record {
+        id: u16,
+        name: &'static str,
+    }
+
+    fn encode(format: OutputFormat, records: &[Record]) -> String {
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(format, &mut output);
+        for record in records {
+            encoder.encode(record).unwrap();
+        }
+        encoder.finish().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn encodes_each_format() {
+        let records = [Record { id: 1, name: "a" }, Record { id: 2, name: "b" }];
+        assert_eq!(encode(OutputFormat::Csv, &records), "id,name\n1,a\n2,b\n");
+        assert_eq!(
+            encode(OutputFormat::Json, &records),
+            "[\n{\"id\":1,\"name\":\"a\"},\n{\"id\":2,\"name\":\"b\"}\n]\n"
+        );
+        assert_eq!(
+            encode(OutputFormat::JsonLines, &records),
+            "{\"id\":1,\"name\":\"a\"}\n{\"id\":2,\"name\":\"b\"}\n"
+        );
+        assert_eq!(encode(OutputFormat::Json, &[]), "[]\n");
+    }
+
+    #[test]
+    fn formats_follow_extensions() {
+        let format = |path: &str| OutputFormat::from_extension(Path::new(path));
+        assert_eq!(format("report.json"), Some(OutputFormat::Json));
+        assert_eq!(format("report.ndjson"), Some(OutputFormat::JsonLines));
+        assert_eq!(format("report"), None);
+        assert_eq!(
+            InputFormat::from_extension(Path::new("events.jsonl")),
+            Some(InputFormat::JsonLines)
+        );
+    }
+}
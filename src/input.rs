@@ -0,0 +1,589 @@
+use csv::StringRecord;
+use csv_core::ReadRecordResult;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::event::Event;
+use crate::format::InputFormat;
+use crate::runner::RunnerError;
+
+const ASYNC_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A source of events, in CSV or JSON Lines.
+pub struct Input {
+    origin: Origin,
+    format: Option<InputFormat>,
+}
+
+enum Origin {
+    Stdin,
+    File(PathBuf),
+    Reader(Box<dyn Read + Send>),
+    AsyncReader(Pin<Box<dyn AsyncRead + Send>>),
+}
+
+/// `-` denotes standard input; anything else is a file path.
+impl From<&str> for Input {
+    fn from(input: &str) -> Self {
+        match input {
+            "-" => Input::stdin(),
+            path => Input::from(PathBuf::from(path)),
+        }
+    }
+}
+
+impl From<PathBuf> for Input {
+    fn from(path: PathBuf) -> Self {
+        Input::new(Origin::File(path))
+    }
+}
+
+impl Input {
+    fn new(origin: Origin) -> Self {
+        Self {
+            origin,
+            format: None,
+        }
+    }
+
+    pub fn stdin() -> Self {
+        Input::new(Origin::Stdin)
+    }
+
+    /// Reads events from any reader, e.g. the output of a decompressor.
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Self {
+        Input::new(Origin::Reader(Box::new(reader)))
+    }
+
+    /// Reads events from an asynchronous reader, e.g. a socket, without blocking the runtime
+    /// while waiting for more data.
+    pub fn from_async_reader(reader: impl AsyncRead + Send + 'static) -> Self {
+        Input::new(Origin::AsyncReader(Box::pin(reader)))
+    }
+
+    /// Reads events in `format` rather than the one implied by the file extension.
+    pub fn with_format(mut self, format: InputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// The format events are read in: the one set explicitly, else the one implied by the file
+    /// extension, else CSV.
+    pub fn format(&self) -> InputFormat {
+        self.format
+            .or_else(|| match &self.origin {
+                Origin::File(path) => InputFormat::from_extension(path),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    fn open(self) -> Result<Source, RunnerError> {
+        let lines = self.format() == InputFormat::JsonLines;
+        Ok(match self.origin {
+            Origin::Stdin => Source::Sync(Box::new(io::stdin())),
+            Origin::File(path) => match File::open(&path) {
+                Ok(file) => Source::Sync(Box::new(file)),
+                Err(source) => return Err(RunnerError::FileError { path, source }),
+            },
+            Origin::Reader(reader) => Source::Sync(reader),
+            Origin::AsyncReader(reader) => {
+                let boundaries = if lines {
+                    Boundaries::Lines
+                } else {
+                    Boundaries::Csv(Box::new(csv_core::Reader::new()))
+                };
+                Source::Async(Box::new(AsyncSource::new(reader, boundaries)))
+            }
+        })
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.origin {
+            Origin::Stdin => f.write_str("standard input"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+            Origin::Reader(_) | Origin::AsyncReader(_) => f.write_str("reader"),
+        }
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Input({})", self)
+    }
+}
+
+enum Source {
+    Sync(Box<dyn Read + Send>),
+    Async(Box<AsyncSource>),
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Sync(reader) => reader.read(buf),
+            Source::Async(source) => source.read(buf),
+        }
+    }
+}
+
+impl Source {
+    /// Makes sure the record after the first `records_consumed` can be read without blocking,
+    /// for asynchronous sources.
+    async fn fill(&mut self, records_consumed: u64) -> io::Result<()> {
+        match self {
+            Source::Async(source) => source.fill(records_consumed).await,
+            Source::Sync(_) => Ok(()),
+        }
+    }
+}
+
+/// How an `AsyncSource` finds where records end.
+enum Boundaries {
+    Csv(Box<csv_core::Reader>),
+    Lines,
+}
+
+impl Boundaries {
+    /// Advances over `input`, which is only empty at the end of the input, and returns the number
+    /// of records it completed.
+    fn count(&mut self, input: &[u8]) -> u64 {
+        match self {
+            Boundaries::Csv(parser) => count_records(parser, input),
+            Boundaries::Lines => input.iter().filter(|&&byte| byte == b'\n').count() as u64,
+        }
+    }
+}
+
+/// Buffers an `AsyncRead` so that a synchronous decoder never has to wait on it.
+///
+/// The complete records received so far are counted as they arrive; before each record is read,
+/// `fill` reads ahead until the buffer holds one more complete record than has been consumed.
+struct AsyncSource {
+    reader: Pin<Box<dyn AsyncRead + Send>>,
+    chunk: Vec<u8>,
+    pending: VecDeque<u8>,
+    boundaries: Boundaries,
+    records_received: u64,
+    eof: bool,
+}
+
+impl AsyncSource {
+    fn new(reader: Pin<Box<dyn AsyncRead + Send>>, boundaries: Boundaries) -> Self {
+        Self {
+            reader,
+            chunk: vec![0; ASYNC_CHUNK_SIZE],
+            pending: VecDeque::new(),
+            boundaries,
+            records_received: 0,
+            eof: false,
+        }
+    }
+
+    async fn fill(&mut self, records_consumed: u64) -> io::Result<()> {
+        while self.records_received <= records_consumed && !self.eof {
+            let read = self.reader.read(&mut self.chunk).await?;
+            self.eof = read == 0;
+            self.records_received += self.boundaries.count(&self.chunk[..read]);
+            self.pending.extend(&self.chunk[..read]);
+        }
+        Ok(())
+    }
+}
+
+/// Advances `parser` over `input`, which is only empty at the end of the input, and returns the
+/// number of records it completed.
+fn count_records(parser: &mut csv_core::Reader, mut input: &[u8]) -> u64 {
+    let mut output = [0; 1024];
+    let mut ends = [0; 64];
+    let mut records = 0;
+    loop {
+        let (result, consumed, _, _) = parser.read_record(input, &mut output, &mut ends);
+        input = &input[consumed..];
+        match result {
+            ReadRecordResult::Record => records += 1,
+            ReadRecordResult::OutputFull | ReadRecordResult::OutputEndsFull => (),
+            ReadRecordResult::InputEmpty | ReadRecordResult::End => return records,
+        }
+    }
+}
+
+impl Read for AsyncSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() && !self.eof {
+            // `fill` guarantees a complete record is buffered, so this is a bug rather than EOF
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.pending.read(buf)
+    }
+}
+
+/// A row as it was read, before parsing.
+pub(crate) enum RawRow {
+    Csv {
+        headers: Arc<StringRecord>,
+        record: StringRecord,
+    },
+    JsonLine {
+        line: u64,
+        text: String,
+    },
+}
+
+impl RawRow {
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            RawRow::Csv { record, .. } => record.position().map(|position| position.line()),
+            RawRow::JsonLine { line, .. } => Some(*line),
+        }
+    }
+
+    /// The row as a single line of its input format, without its terminator.
+    pub fn text(&self) -> String {
+        match self {
+            RawRow::Csv { record, .. } => {
+                let mut writer = csv::WriterBuilder::new()
+                    .terminator(csv::Terminator::Any(b'\n'))
+                    .from_writer(Vec::new());
+                let bytes = match writer.write_record(record) {
+                    Ok(()) => writer.into_inner().unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                String::from_utf8_lossy(&bytes).trim_end().to_owned()
+            }
+            RawRow::JsonLine { text, .. } => text.clone(),
+        }
+    }
+
+    /// The value of the column or field `name`, if the row has one.
+    pub fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match self {
+            RawRow::Csv { headers, record } => headers
+                .iter()
+                .position(|header| header == name)
+                .and_then(|index| record.get(index))
+                .map(Cow::Borrowed),
+            RawRow::JsonLine { text, .. } => {
+                match serde_json::from_str::<Map<String, Value>>(text)
+                    .ok()?
+                    .remove(name)?
+                {
+                    Value::String(value) => Some(Cow::Owned(value)),
+                    Value::Number(value) => Some(Cow::Owned(value.to_string())),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Why a row could not be parsed into an event.
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    Csv(csv::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Csv(e) => e.fmt(f),
+            ParseError::Json(e) => e.fmt(f),
+        }
+    }
+}
+
+/// A row read from an input, along with the result of parsing it into an event.
+pub(crate) struct Row {
+    pub raw: RawRow,
+    pub event: Result<Event, ParseError>,
+}
+
+/// Reads the rows of one input in its format.
+enum Decoder {
+    Csv {
+        reader: csv::Reader<Source>,
+        headers: Arc<StringRecord>,
+    },
+    JsonLines {
+        reader: BufReader<Source>,
+        lines_read: u64,
+    },
+}
+
+impl Decoder {
+    async fn new(source: Source, format: InputFormat) -> io::Result<Self> {
+        Ok(match format {
+            InputFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .trim(csv::Trim::All)
+                    .from_reader(source);
+                reader.get_mut().fill(0).await?;
+                let headers = reader.headers().map_err(io::Error::from)?.clone();
+                Decoder::Csv {
+                    reader,
+                    headers: Arc::new(headers),
+                }
+            }
+            InputFormat::JsonLines => Decoder::JsonLines {
+                reader: BufReader::new(source),
+                lines_read: 0,
+            },
+        })
+    }
+
+    /// Returns the next row, `Ok(None)` at the end of the input, or a failure to read a row that
+    /// is not an I/O error (such as a CSV row with the wrong number of fields) as `Err`.
+    async fn next_row(&mut self) -> io::Result<Option<Result<Row, csv::Error>>> {
+        match self {
+            Decoder::Csv { reader, headers } => {
+                let records_consumed = reader.position().record();
+                reader.get_mut().fill(records_consumed).await?;
+                let mut record = StringRecord::new();
+                match reader.read_record(&mut record) {
+                    Ok(true) => {
+                        let event = record.deserialize(Some(headers)).map_err(ParseError::Csv);
+                        Ok(Some(Ok(Row {
+                            raw: RawRow::Csv {
+                                headers: Arc::clone(headers),
+                                record,
+                            },
+                            event,
+                        })))
+                    }
+                    Ok(false) => Ok(None),
+                    Err(e) if e.is_io_error() => Err(e.into()),
+                    Err(e) => Ok(Some(Err(e))),
+                }
+            }
+            Decoder::JsonLines { reader, lines_read } => loop {
+                reader.get_mut().fill(*lines_read).await?;
+                let mut bytes = Vec::new();
+                if reader.read_until(b'\n', &mut bytes)? == 0 {
+                    return Ok(None);
+                }
+                *lines_read += 1;
+                let text = String::from_utf8_lossy(&bytes).trim().to_owned();
+                // Blank lines are skipped, as they are in CSV
+                if text.is_empty() {
+                    continue;
+                }
+                return Ok(Some(Ok(Row {
+                    event: decode_json(&bytes).map_err(ParseError::Json),
+                    raw: RawRow::JsonLine {
+                        line: *lines_read,
+                        text,
+                    },
+                })));
+            },
+        }
+    }
+}
+
+/// Parses a JSON object with the same fields as the CSV columns into an event.
+///
+/// Amounts may be JSON numbers as well as strings. Numbers keep their digits as written, as
+/// `serde_json` is built with `arbitrary_precision`, so they are parsed exactly like strings.
+fn decode_json(bytes: &[u8]) -> Result<Event, serde_json::Error> {
+    let mut fields: Map<String, Value> = serde_json::from_slice(bytes)?;
+    if let Some(Value::Number(amount)) = fields.get("amount") {
+        let amount = Value::String(amount.to_string());
+        fields.insert("amount".to_owned(), amount);
+    }
+    Event::deserialize(Value::Object(fields))
+}
+
+/// Reads rows from each input in turn, opening inputs only once the previous one is exhausted.
+pub(crate) struct InputRows {
+    inputs: std::vec::IntoIter<Input>,
+    current: Option<(Decoder, String)>,
+}
+
+impl InputRows {
+    pub fn new(inputs: Vec<Input>) -> Self {
+        Self {
+            inputs: inputs.into_iter(),
+            current: None,
+        }
+    }
+
+    /// Returns the next row, `Ok(None)` once all inputs are exhausted, or a failure to read a
+    /// row that is not an I/O error (such as a row with the wrong number of fields) as `Err`.
+    pub async fn next_row(&mut self) -> Result<Option<Result<Row, csv::Error>>, RunnerError> {
+        loop {
+            let (decoder, name) = match &mut self.current {
+                Some(current) => current,
+                None => match self.inputs.next() {
+                    None => return Ok(None),
+                    Some(input) => {
+                        let name = input.to_string();
+                        let format = input.format();
+                        let decoder = match Decoder::new(input.open()?, format).await {
+                            Ok(decoder) => decoder,
+                            Err(source) => {
+                                return Err(RunnerError::InputError {
+                                    input: name,
+                                    source,
+                                })
+                            }
+                        };
+                        self.current.insert((decoder, name))
+                    }
+                },
+            };
+            match decoder.next_row().await {
+                Ok(Some(row)) => return Ok(Some(row)),
+                Ok(None) => self.current = None,
+                Err(source) => {
+                    return Err(RunnerError::InputError {
+                        input: name.clone(),
+                        source,
+                    })
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    /// Async reader which returns at most `chunk` bytes per read, to split records across reads.
+    struct Trickle {
+        data: &'static [u8],
+        chunk: usize,
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let len = self.chunk.min(self.data.len()).min(buf.remaining());
+            buf.put_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn read_all(input: Input) -> Vec<(Option<u64>, Event)> {
+        let mut rows = InputRows::new(vec![input]);
+        let mut events = Vec::new();
+        while let Some(row) = rows.next_row().await.unwrap() {
+            let row = row.unwrap();
+            events.push((row.raw.line(), row.event.unwrap()));
+        }
+        events
+    }
+
+    const INPUT: &[u8] = b"type, client, tx, amount\r\n\
+        deposit, 1, 1, 1.0\r\n\
+        \r\n\
+        withdrawal, 1, 2, 0.5\r\n\
+        dispute, 1, 1,";
+
+    #[tokio::test]
+    async fn async_reader_matches_sync_reader() {
+        let expected = read_all(Input::from_reader(INPUT)).await;
+        assert_eq!(expected.len(), 3);
+        for chunk in [1, 3, 7, INPUT.len()] {
+            let input = Input::from_async_reader(Trickle { data: INPUT, chunk });
+            assert_eq!(read_all(input).await, expected, "chunk size {}", chunk);
+        }
+    }
+
+    const JSON_LINES_INPUT: &[u8] =
+        b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1.0}\n\
+        \n\
+        {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": \"0.5\"}\r\n\
+        {\"type\": \"dispute\", \"client\": 1, \"tx\": 1}";
+
+    #[tokio::test]
+    async fn json_lines_input_matches_csv_input() {
+        let csv = read_all(Input::from_reader(INPUT)).await;
+        let events: Vec<Event> = csv.into_iter().map(|(_, event)| event).collect();
+        let expected =
+            read_all(Input::from_reader(JSON_LINES_INPUT).with_format(InputFormat::JsonLines))
+                .await;
+        assert_eq!(
+            expected.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            vec![Some(1), Some(3), Some(4)]
+        );
+        assert_eq!(
+            expected
+                .iter()
+                .map(|(_, event)| event.clone())
+                .collect::<Vec<_>>(),
+            events
+        );
+        for chunk in [1, 3, 7, JSON_LINES_INPUT.len()] {
+            let input = Input::from_async_reader(Trickle {
+                data: JSON_LINES_INPUT,
+                chunk,
+            })
+            .with_format(InputFormat::JsonLines);
+            assert_eq!(read_all(input).await, expected, "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn json_number_amounts_keep_every_digit() {
+        let event = decode_json(
+            b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 12345678901234.567}",
+        )
+        .unwrap();
+        match event {
+            Event::Deposit(deposit) => {
+                assert_eq!(deposit.amount.to_string(), "12345678901234.5670")
+            }
+            other => panic!("expected a deposit, got {:?}", other),
+        }
+        assert!(
+            decode_json(b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1e3}")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn format_follows_file_extension() {
+        assert_eq!(Input::from("events.jsonl").format(), InputFormat::JsonLines);
+        assert_eq!(Input::from("events").format(), InputFormat::Csv);
+        assert_eq!(
+            Input::from("events.jsonl")
+                .with_format(InputFormat::Csv)
+                .format(),
+            InputFormat::Csv
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_file_reports_path_and_cause() {
+        let path = std::env::temp_dir().join("transaction_processor-does-not-exist.csv");
+        let mut rows = InputRows::new(vec![Input::from(path.clone())]);
+        match rows.next_row().await {
+            Err(RunnerError::FileError {
+                path: error_path,
+                source,
+            }) => {
+                assert_eq!(error_path, path);
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            _ => panic!("expected a file error"),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::event::{Event, EventType};
+use crate::store::{Changeset, Client, Credit, Store, StoreError, Transaction};
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Journal could not be accessed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Journal entry {index} is malformed: {source}")]
+    Malformed {
+        index: usize,
+        source: serde_json::Error,
+    },
+    #[error("Journal entry {0} does not follow from the state left by the previous entries")]
+    Inconsistent(usize),
+    #[error("{0}")]
+    StoreError(#[from] StoreError),
+}
+
+/// A record's value before and after an event was applied; `before` is `None` if it was created.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Change<T> {
+    pub before: Option<T>,
+    pub after: T,
+}
+
+/// Every record changed by one successfully processed event.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct JournalEntry {
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    pub client: u16,
+    pub tx: u32,
+    /// Reason code given for an adjustment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub clients: Vec<Change<Client>>,
+    pub transactions: Vec<Change<Transaction>>,
+    /// Fees credited to the house account, applied to whatever balance it had at the time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub credits: Vec<Credit>,
+}
+
+impl JournalEntry {
+    pub(crate) fn new(
+        event: &Event,
+        (client_before, transaction_before): (Option<Client>, Option<Transaction>),
+        (client_after, transaction_after): (Client, Transaction),
+    ) -> Self {
+        Self {
+            event_type: event.event_type(),
+            client: event.client(),
+            tx: event.tx(),
+            reason: match event {
+                Event::Adjustment(adjustment) => Some(adjustment.reason.clone()),
+                _ => None,
+            },
+            clients: vec![Change {
+                before: client_before,
+                after: client_after,
+            }],
+            transactions: vec![Change {
+                before: transaction_before,
+                after: transaction_after,
+            }],
+            credits: Vec::new(),
+        }
+    }
+
+    /// Adds the change to a second client's account, the counterparty of the event.
+    pub(crate) fn with_client(mut self, before: Option<Client>, after: Client) -> Self {
+        self.clients.push(Change { before, after });
+        self
+    }
+
+    /// Adds a credit to a client's account, applied along with the other changes.
+    pub(crate) fn with_credit(mut self, credit: Credit) -> Self {
+        self.credits.push(credit);
+        self
+    }
+
+    /// Whether the entry changed the client's account.
+    pub fn affects_client(&self, client: u16) -> bool {
+        self.clients.iter().any(|change| change.after.id == client)
+            || self.credits.iter().any(|credit| credit.client == client)
+    }
+
+    /// The records as they were left by the entry.
+    pub fn changeset(&self) -> Changeset {
+        Changeset {
+            clients: self.clients.iter().map(|c| c.after.clone()).collect(),
+            transactions: self.transactions.iter().map(|t| t.after.clone()).collect(),
+            credits: self.credits.clone(),
+        }
+    }
+}
+
+/// Append-only log of the state changes made by every applied event.
+pub trait Journal {
+    fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError>;
+
+    /// All entries, in the order they were appended.
+    fn entries(&self) -> Result<Vec<JournalEntry>, JournalError>;
+
+    /// The entries which changed `client`'s account, in the order they were appended.
+    fn history(&self, client: u16) -> Result<Vec<JournalEntry>, JournalError> {
+        let mut entries = self.entries()?;
+        entries.retain(|entry| entry.affects_client(client));
+        Ok(entries)
+    }
+
+    fn flush(&mut self) -> Result<(), JournalError> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryJournal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal for InMemoryJournal {
+    fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        self.entries.push(entry.clone());
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<JournalEntry>, JournalError> {
+        Ok(self.entries.clone())
+    }
+}
+
+/// Journal kept in a JSON Lines file, one entry per line. Opening an existing file appends to it.
+pub struct FileJournal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl FileJournal {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Reads the entries of a journal file without opening it for writing.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<JournalEntry>, JournalError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .map_err(|source| JournalError::Malformed { index, source })?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+impl Journal for FileJournal {
+    fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        serde_json::to_writer(&mut self.writer, entry).map_err(io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<JournalEntry>, JournalError> {
+        // Entries still buffered in `writer` are not visible until flushed
+        Self::read(&self.path)
+    }
+
+    fn flush(&mut self) -> Result<(), JournalError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Rebuilds a store by applying every entry in order.
+///
+/// Each entry's `before` values must match what the store holds when it is applied, so a journal
+/// with missing or reordered entries is rejected with `JournalError::Inconsistent`.
+pub fn replay<S: Store>(entries: &[JournalEntry], store: &mut S) -> Result<(), JournalError> {
+    for (index, entry) in entries.iter().enumerate() {
+        for change in &entry.clients {
+            if store.get_client(change.after.id)? != change.before {
+                return Err(JournalError::Inconsistent(index));
+            }
+        }
+        for change in &entry.transactions {
+            if store.get_transaction(change.after.id)? != change.before {
+                return Err(JournalError::Inconsistent(index));
+            }
+        }
+        store.apply(entry.changeset())?;
+    }
+    store.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::currency::Currency;
+    use crate::event::{FundsEvent, ReferenceEvent};
+    use crate::store::{Balance, DisputeState, InMemoryStore, TransactionKind};
+
+    fn client(id: u16, available: i64, held: i64) -> Client {
+        let mut client = Client {
+            id,
+            ..Client::default()
+        };
+        *client.balance_mut(Currency::UNSPECIFIED) = Balance {
+            available: Amount::from_units(available),
+            held: Amount::from_units(held),
+        };
+        client
+    }
+
+    fn transaction(id: u32, client: u16, amount: i64, state: DisputeState) -> Transaction {
+        Transaction {
+            id,
+            client,
+            kind: TransactionKind::Deposit,
+            amount: Amount::from_units(amount),
+            state,
+            currency: Currency::UNSPECIFIED,
+            counterparty: None,
+            fee: None,
+            created_at: None,
+        }
+    }
+
+    fn entries() -> Vec<JournalEntry> {
+        let deposit = Event::Deposit(FundsEvent {
+            client: 1,
+            tx: 1,
+            amount: Amount::from_units(5),
+            currency: Currency::UNSPECIFIED,
+            timestamp: None,
+        });
+        let other = Event::Deposit(FundsEvent {
+            client: 2,
+            tx: 2,
+            amount: Amount::from_units(7),
+            currency: Currency::UNSPECIFIED,
+            timestamp: None,
+        });
+        let dispute = Event::Dispute(ReferenceEvent {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        });
+        vec![
+            JournalEntry::new(
+                &deposit,
+                (None, None),
+                (client(1, 5, 0), transaction(1, 1, 5, DisputeState::Settled)),
+            ),
+            JournalEntry::new(
+                &other,
+                (None, None),
+                (client(2, 7, 0), transaction(2, 2, 7, DisputeState::Settled)),
+            ),
+            JournalEntry::new(
+                &dispute,
+                (
+                    Some(client(1, 5, 0)),
+                    Some(transaction(1, 1, 5, DisputeState::Settled)),
+                ),
+                (
+                    client(1, 0, 5),
+                    transaction(1, 1, 5, DisputeState::Disputed),
+                ),
+            ),
+        ]
+    }
+
+    #[test]
+    fn history_only_includes_client_entries() {
+        let mut journal = InMemoryJournal::default();
+        for entry in entries() {
+            journal.append(&entry).unwrap();
+        }
+        let history = journal.history(1).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].event_type, EventType::Deposit);
+        assert_eq!(history[1].event_type, EventType::Dispute);
+        assert_eq!(history[1].clients[0].after, client(1, 0, 5));
+    }
+
+    #[test]
+    fn replay_rebuilds_final_state() {
+        let mut store = InMemoryStore::default();
+        replay(&entries(), &mut store).unwrap();
+        assert_eq!(store.get_client(1), Ok(Some(client(1, 0, 5))));
+        assert_eq!(store.get_client(2), Ok(Some(client(2, 7, 0))));
+        assert_eq!(
+            store.get_transaction(1),
+            Ok(Some(transaction(1, 1, 5, DisputeState::Disputed)))
+        );
+    }
+
+    #[test]
+    fn replay_rejects_missing_entries() {
+        let mut entries = entries();
+        entries.remove(0);
+        let mut store = InMemoryStore::default();
+        assert!(matches!(
+            replay(&entries, &mut store),
+            Err(JournalError::Inconsistent(1))
+        ));
+    }
+
+    #[test]
+    fn file_journal_round_trips_entries() {
+        let path = std::env::temp_dir().join(format!(
+            "transaction_processor-{}-journal.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut journal = FileJournal::open(&path).unwrap();
+            journal.append(&entries()[0]).unwrap();
+            journal.flush().unwrap();
+        }
+        let mut journal = FileJournal::open(&path).unwrap();
+        for entry in &entries()[1..] {
+            journal.append(entry).unwrap();
+        }
+        journal.flush().unwrap();
+        assert_eq!(journal.entries().unwrap(), entries());
+        let _ = std::fs::remove_file(&path);
+    }
+}
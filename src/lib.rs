@@ -1,13 +1,64 @@
-use logger::StderrLogger;
-use runner::{CsvSingleProcessRunner, Runner, RunnerError};
-use store::InMemoryStore;
+use std::sync::{Arc, Mutex};
 
+use event::Event;
+use logger::NoopLogger;
+use policy::Policy;
+use runner::{Configure, CsvSingleProcessRunner, RunOutcome, Runner, RunnerError};
+use store::{ClientOrder, InMemoryStore, Store};
+
+pub mod amount;
+pub mod currency;
+pub mod event;
+pub mod fees;
+pub mod format;
+pub mod input;
+pub mod journal;
+pub mod limits;
 pub mod logger;
+pub mod policy;
 pub mod processor;
+pub mod rates;
+pub mod rejection;
 pub mod runner;
+pub mod snapshot;
 pub mod store;
 
-pub async fn process_events_from_file(input_file: &str) -> Result<(), RunnerError> {
-    let mut runner = CsvSingleProcessRunner::<InMemoryStore, StderrLogger>::new(input_file);
-    runner.run().await
+/// Processes `events` in order against new in-memory accounts under the default policy.
+pub async fn process_events(
+    events: impl IntoIterator<Item = Event>,
+) -> Result<RunOutcome, RunnerError> {
+    let mut store = InMemoryStore::default();
+    runner::process_events(events, &mut store, &Policy::default(), &NoopLogger).await
+}
+
+pub async fn process_events_from_file(input_file: &str) -> Result<RunOutcome, RunnerError> {
+    let runner = CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(input_file);
+    run_to_outcome(runner).await
+}
+
+#[cfg(feature = "sqlite")]
+pub async fn process_events_from_file_into_database(
+    input_file: &str,
+    database_file: &str,
+) -> Result<RunOutcome, RunnerError> {
+    let store = store::SqliteStore::open(database_file)?;
+    let runner = CsvSingleProcessRunner::<_, NoopLogger>::with_store(input_file, store);
+    run_to_outcome(runner).await
+}
+
+async fn run_to_outcome<S: Store + Send>(
+    runner: CsvSingleProcessRunner<S, NoopLogger>,
+) -> Result<RunOutcome, RunnerError> {
+    let rejections = Arc::new(Mutex::new(Vec::new()));
+    let mut runner = runner.with_rejections(Arc::clone(&rejections));
+    let summary = runner.run().await?;
+    let rejections = match rejections.lock() {
+        Ok(rejections) => rejections.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    Ok(RunOutcome {
+        clients: runner.store().dump_clients_sorted(ClientOrder::Id)?,
+        summary,
+        rejections,
+    })
 }
@@ -0,0 +1,213 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::amount::Amount;
+use crate::currency::Currency;
+
+#[derive(Error, Debug)]
+pub enum LimitsError {
+    #[error("Limits file could not be accessed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Limits file is malformed: {0}")]
+    Malformed(#[from] csv::Error),
+    #[error("Limits file has a limit which is not positive for {}", scope(*.client, *.currency))]
+    NonPositiveLimit {
+        client: Option<u16>,
+        currency: Option<Currency>,
+    },
+    #[error("Limits file has more than one row for {}", scope(*.client, *.currency))]
+    DuplicateLimits {
+        client: Option<u16>,
+        currency: Option<Currency>,
+    },
+}
+
+fn scope(client: Option<u16>, currency: Option<Currency>) -> String {
+    match (client, currency) {
+        (Some(client), Some(currency)) => format!("client {} in {}", client, currency),
+        (Some(client), None) => format!("client {}", client),
+        (None, Some(currency)) => format!("every client in {}", currency),
+        (None, None) => "every client".to_owned(),
+    }
+}
+
+/// The most a client may move in one currency; `None` is unlimited.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Limits {
+    /// Largest amount of a single deposit.
+    pub max_deposit: Option<Amount>,
+    /// Largest amount of a single withdrawal.
+    pub max_withdrawal: Option<Amount>,
+    /// Largest total withdrawn on one UTC day.
+    pub max_daily_withdrawal: Option<Amount>,
+}
+
+/// A row of the limits file.
+#[derive(Deserialize)]
+struct LimitsRecord {
+    client: Option<u16>,
+    currency: Option<Currency>,
+    max_deposit: Option<Amount>,
+    max_withdrawal: Option<Amount>,
+    max_daily_withdrawal: Option<Amount>,
+}
+
+/// Deposit and withdrawal limits, by default or for particular clients and currencies.
+///
+/// The most specific row applies on its own: one for the client and currency, then one for
+/// the client, then one for the currency, then the default. Without any row, nothing is
+/// limited.
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct LimitTable {
+    limits: HashMap<(Option<u16>, Option<Currency>), Limits>,
+}
+
+impl LimitTable {
+    /// Sets the limits for `client`, or every client if `None`, in `currency`, or every
+    /// currency if `None`.
+    pub fn with_limits(
+        mut self,
+        client: Option<u16>,
+        currency: Option<Currency>,
+        limits: Limits,
+    ) -> Self {
+        self.limits.insert((client, currency), limits);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// The limits which apply to `client` in `currency`.
+    pub fn get(&self, client: u16, currency: Currency) -> Limits {
+        [
+            (Some(client), Some(currency)),
+            (Some(client), None),
+            (None, Some(currency)),
+            (None, None),
+        ]
+        .iter()
+        .find_map(|key| self.limits.get(key))
+        .copied()
+        .unwrap_or_default()
+    }
+
+    /// Reads a CSV table with `client`, `currency`, `max_deposit`, `max_withdrawal` and
+    /// `max_daily_withdrawal` columns, e.g. `7,EUR,1000,,5000`; a blank client or currency
+    /// applies to all of them, and a blank limit is unlimited.
+    pub fn read<R: Read>(input: R) -> Result<Self, LimitsError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        let mut table = LimitTable::default();
+        for record in reader.deserialize() {
+            let LimitsRecord {
+                client,
+                currency,
+                max_deposit,
+                max_withdrawal,
+                max_daily_withdrawal,
+            } = record?;
+            let limits = Limits {
+                max_deposit,
+                max_withdrawal,
+                max_daily_withdrawal,
+            };
+            if [max_deposit, max_withdrawal, max_daily_withdrawal]
+                .iter()
+                .flatten()
+                .any(|limit| !limit.is_positive())
+            {
+                return Err(LimitsError::NonPositiveLimit { client, currency });
+            }
+            if table.limits.insert((client, currency), limits).is_some() {
+                return Err(LimitsError::DuplicateLimits { client, currency });
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LimitsError> {
+        Self::read(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn amount(s: &str) -> Option<Amount> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn most_specific_limits_apply() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let table = LimitTable::read(
+            &b"client, currency, max_deposit, max_withdrawal, max_daily_withdrawal\n\
+               , , 100, 50, 200\n\
+               , USD, 1000, , \n\
+               7, , , 10, \n\
+               7, EUR, 5, , 20\n"[..],
+        )
+        .unwrap();
+        assert_eq!(
+            table.get(1, eur),
+            Limits {
+                max_deposit: amount("100"),
+                max_withdrawal: amount("50"),
+                max_daily_withdrawal: amount("200"),
+            }
+        );
+        assert_eq!(table.get(1, usd).max_deposit, amount("1000"));
+        assert_eq!(table.get(1, usd).max_withdrawal, None);
+        assert_eq!(table.get(7, usd).max_withdrawal, amount("10"));
+        assert_eq!(table.get(7, usd).max_deposit, None);
+        assert_eq!(table.get(7, eur).max_daily_withdrawal, amount("20"));
+        assert_eq!(LimitTable::default().get(1, eur), Limits::default());
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        assert!(matches!(
+            LimitTable::read(
+                &b"client,currency,max_deposit,max_withdrawal,max_daily_withdrawal\n\
+                   3,,1,,\n3,,2,,\n"[..]
+            ),
+            Err(LimitsError::DuplicateLimits {
+                client: Some(3),
+                currency: None,
+            })
+        ));
+        assert!(matches!(
+            LimitTable::read(
+                &b"client,currency,max_deposit,max_withdrawal,max_daily_withdrawal\n,,x,,\n"[..]
+            ),
+            Err(LimitsError::Malformed(_))
+        ));
+        assert!(matches!(
+            LimitTable::read(
+                &b"client,currency,max_deposit,max_withdrawal,max_daily_withdrawal\n,EUR,,-5,\n"[..]
+            ),
+            Err(LimitsError::NonPositiveLimit {
+                client: None,
+                currency: Some(_),
+            })
+        ));
+        assert!(matches!(
+            LimitTable::read(
+                &b"client,currency,max_deposit,max_withdrawal,max_daily_withdrawal\n2,,,,0\n"[..]
+            ),
+            Err(LimitsError::NonPositiveLimit {
+                client: Some(2),
+                currency: None,
+            })
+        ));
+    }
+}
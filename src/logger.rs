@@ -1,19 +1,345 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::event::EventType;
+
+/// Severity of a log record, from least to most verbose.
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+/// Structured context attached to a log record.
+#[derive(Serialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Fields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx: Option<u32>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<EventType>,
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, " line={}", line)?;
+        }
+        if let Some(client) = self.client {
+            write!(f, " client={}", client)?;
+        }
+        if let Some(tx) = self.tx {
+            write!(f, " tx={}", tx)?;
+        }
+        if let Some(event_type) = self.event_type {
+            write!(f, " type={}", event_type)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Record<'a> {
+    pub level: Level,
+    pub fields: &'a Fields,
+    pub message: fmt::Arguments<'a>,
+}
+
+/// Sink for diagnostic output.
+///
+/// Messages are passed as `format_args!` so that records below a logger's level are never
+/// formatted. Implementations only need `enabled` and `log`; callers use the per-level methods.
 pub trait Logger {
-    fn error(&self, message: String) -> ();
+    fn enabled(&self, level: Level) -> bool;
+    fn log(&self, record: &Record<'_>);
+
+    fn emit(&self, level: Level, fields: Fields, message: fmt::Arguments<'_>) {
+        if self.enabled(level) {
+            self.log(&Record {
+                level,
+                fields: &fields,
+                message,
+            });
+        }
+    }
+
+    fn error(&self, fields: Fields, message: fmt::Arguments<'_>) {
+        self.emit(Level::Error, fields, message)
+    }
+
+    fn warn(&self, fields: Fields, message: fmt::Arguments<'_>) {
+        self.emit(Level::Warn, fields, message)
+    }
+
+    fn info(&self, fields: Fields, message: fmt::Arguments<'_>) {
+        self.emit(Level::Info, fields, message)
+    }
+
+    fn debug(&self, fields: Fields, message: fmt::Arguments<'_>) {
+        self.emit(Level::Debug, fields, message)
+    }
+
+    fn trace(&self, fields: Fields, message: fmt::Arguments<'_>) {
+        self.emit(Level::Trace, fields, message)
+    }
+}
+
+/// Writes human-readable records at or above `max_level` to standard error.
+pub struct StderrLogger {
+    max_level: Level,
+}
+
+impl StderrLogger {
+    pub fn new(max_level: Level) -> Self {
+        Self { max_level }
+    }
+}
+
+impl Default for StderrLogger {
+    fn default() -> Self {
+        Self::new(Level::Warn)
+    }
 }
 
-#[derive(Default)]
-pub struct StderrLogger;
 impl Logger for StderrLogger {
-    fn error(&self, message: String) -> () {
-        eprintln!("{}", message);
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.max_level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        eprintln!("{:5} {}{}", record.level, record.message, record.fields);
+    }
+}
+
+/// Writes records at or above `max_level` as JSON Lines, one object per record.
+pub struct JsonLinesLogger<W: Write> {
+    max_level: Level,
+    output: Mutex<W>,
+}
+
+impl<W: Write> JsonLinesLogger<W> {
+    pub fn new(output: W, max_level: Level) -> Self {
+        Self {
+            max_level,
+            output: Mutex::new(output),
+        }
+    }
+}
+
+impl Default for JsonLinesLogger<io::Stderr> {
+    fn default() -> Self {
+        Self::new(io::stderr(), Level::Warn)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    level: Level,
+    #[serde(serialize_with = "collect_str")]
+    message: &'a fmt::Arguments<'a>,
+    #[serde(flatten)]
+    fields: &'a Fields,
+}
+
+fn collect_str<S: Serializer>(
+    message: &&fmt::Arguments<'_>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(message)
+}
+
+impl<W: Write> Logger for JsonLinesLogger<W> {
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.max_level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let json = JsonRecord {
+            level: record.level,
+            message: &record.message,
+            fields: record.fields,
+        };
+        // Logging is best effort: a broken log output must not abort processing
+        if let Ok(mut output) = self.output.lock() {
+            if serde_json::to_writer(&mut *output, &json).is_ok() {
+                let _ = output.write_all(b"\n");
+            }
+        }
+    }
+}
+
+/// A record kept by a `MemoryLogger`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct OwnedRecord {
+    pub level: Level,
+    pub fields: Fields,
+    pub message: String,
+}
+
+/// Keeps every record in memory, e.g. so tests can assert on what was logged.
+#[derive(Default)]
+pub struct MemoryLogger {
+    records: Mutex<Vec<OwnedRecord>>,
+}
+
+impl MemoryLogger {
+    pub fn records(&self) -> Vec<OwnedRecord> {
+        match self.records.lock() {
+            Ok(records) => records.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+impl Logger for MemoryLogger {
+    fn enabled(&self, _level: Level) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let record = OwnedRecord {
+            level: record.level,
+            fields: *record.fields,
+            message: record.message.to_string(),
+        };
+        match self.records.lock() {
+            Ok(mut records) => records.push(record),
+            Err(poisoned) => poisoned.into_inner().push(record),
+        }
+    }
+}
+
+/// Forwards records to the `tracing` ecosystem, leaving filtering to the installed subscriber.
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+pub struct TracingLogger;
+
+#[cfg(feature = "tracing")]
+impl Logger for TracingLogger {
+    fn enabled(&self, level: Level) -> bool {
+        match level {
+            Level::Error => tracing::enabled!(tracing::Level::ERROR),
+            Level::Warn => tracing::enabled!(tracing::Level::WARN),
+            Level::Info => tracing::enabled!(tracing::Level::INFO),
+            Level::Debug => tracing::enabled!(tracing::Level::DEBUG),
+            Level::Trace => tracing::enabled!(tracing::Level::TRACE),
+        }
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        macro_rules! forward {
+            ($level:expr) => {
+                tracing::event!(
+                    $level,
+                    line = record.fields.line,
+                    client = record.fields.client,
+                    tx = record.fields.tx,
+                    event_type = record
+                        .fields
+                        .event_type
+                        .as_ref()
+                        .map(tracing::field::display),
+                    "{}",
+                    record.message
+                )
+            };
+        }
+        match record.level {
+            Level::Error => forward!(tracing::Level::ERROR),
+            Level::Warn => forward!(tracing::Level::WARN),
+            Level::Info => forward!(tracing::Level::INFO),
+            Level::Debug => forward!(tracing::Level::DEBUG),
+            Level::Trace => forward!(tracing::Level::TRACE),
+        }
     }
 }
 
 #[derive(Default)]
 pub struct NoopLogger;
 impl Logger for NoopLogger {
-    fn error(&self, _message: String) -> () {
-        ();
+    fn enabled(&self, _level: Level) -> bool {
+        false
+    }
+
+    fn log(&self, _record: &Record<'_>) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields() -> Fields {
+        Fields {
+            line: Some(3),
+            client: Some(1),
+            tx: Some(2),
+            event_type: Some(EventType::Withdrawal),
+        }
+    }
+
+    #[test]
+    fn memory_logger_keeps_records_with_fields() {
+        let logger = MemoryLogger::default();
+        logger.warn(fields(), format_args!("rejected {}", 2));
+        logger.debug(Fields::default(), format_args!("progress"));
+        assert_eq!(
+            logger.records(),
+            vec![
+                OwnedRecord {
+                    level: Level::Warn,
+                    fields: fields(),
+                    message: "rejected 2".to_owned(),
+                },
+                OwnedRecord {
+                    level: Level::Debug,
+                    fields: Fields::default(),
+                    message: "progress".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn json_lines_logger_filters_by_level() {
+        let logger = JsonLinesLogger::new(Vec::new(), Level::Info);
+        logger.warn(fields(), format_args!("rejected"));
+        logger.info(Fields::default(), format_args!("done"));
+        logger.debug(Fields::default(), format_args!("hidden"));
+        let output = String::from_utf8(logger.output.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "{\"level\":\"warn\",\"message\":\"rejected\",\"line\":3,\"client\":1,\"tx\":2,\
+             \"type\":\"withdrawal\"}\n\
+             {\"level\":\"info\",\"message\":\"done\"}\n"
+        );
+    }
+
+    #[test]
+    fn fields_format_as_key_value_pairs() {
+        assert_eq!(
+            fields().to_string(),
+            " line=3 client=1 tx=2 type=withdrawal"
+        );
+        assert_eq!(Fields::default().to_string(), "");
     }
 }
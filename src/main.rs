@@ -1,12 +1,669 @@
-use std::env;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use transaction_processor::amount::Amount;
+use transaction_processor::currency::Currency;
+use transaction_processor::fees::{FeeSchedule, FeesError};
+use transaction_processor::format::{InputFormat, OutputFormat};
+use transaction_processor::input::Input;
+use transaction_processor::journal::{self, FileJournal, JournalEntry, JournalError};
+use transaction_processor::limits::{LimitTable, LimitsError};
+use transaction_processor::logger::{JsonLinesLogger, Level, Logger, StderrLogger};
+use transaction_processor::policy::Policy;
+use transaction_processor::rates::{RateTable, RatesError, RoundingMode};
+use transaction_processor::rejection::{CsvRejectionSink, JsonLinesRejectionSink};
+use transaction_processor::runner::{
+    self, Configure, CsvSingleProcessRunner, RunConfig, RunSummary, Runner, RunnerError,
+    ShardedRunner,
+};
+use transaction_processor::snapshot::{Snapshot, SnapshotError};
+use transaction_processor::store::{ClientOrder, ConcurrentStore, InMemoryStore, Store};
+
+const EXIT_REJECTED: i32 = 1;
+const EXIT_PARSE_FAILURE: i32 = 3;
+const EXIT_IO_FAILURE: i32 = 4;
+const EXIT_FAILURE: i32 = 5;
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  all events were applied
+  1  completed, but some events were rejected
+  2  invalid command-line arguments
+  3  completed, but some rows could not be parsed
+  4  an input or output could not be read or written
+  5  processing failed (e.g. a store error)";
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Processes client transaction events into account balances"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Processes events from one or more inputs and writes the final client balances
+    #[command(after_help = EXIT_CODES_HELP)]
+    Process(Box<ProcessArgs>),
+    /// Prints the journal entries which changed a client's account, as JSON Lines
+    History(HistoryArgs),
+    /// Rebuilds the client balances purely from a journal
+    Replay(ReplayArgs),
+    /// Prints the transaction ledger rebuilt from a journal, optionally for a single client
+    Transactions(TransactionsArgs),
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    /// Input files, processed in order against the same accounts; `-` reads standard input
+    #[arg(value_name = "INPUT", required = true)]
+    inputs: Vec<String>,
+
+    /// Format of every input [default: jsonl for .jsonl files, otherwise csv]
+    #[arg(short, long, value_enum, value_name = "FORMAT")]
+    input_format: Option<EventFormat>,
+
+    /// Writes the client report to PATH instead of standard output
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Format of the client report [default: json for .json files, jsonl for .jsonl files,
+    /// otherwise csv]
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Order of the client report; ties are broken by client ID
+    #[arg(long, value_enum, default_value_t = SortOrder::Id)]
+    sort: SortOrder,
+
+    /// Also writes the final transaction ledger to PATH, in the format implied by its extension
+    #[arg(short, long, value_name = "PATH")]
+    transactions: Option<PathBuf>,
+
+    /// Writes a report of every row which could not be parsed or was rejected to PATH
+    #[arg(short, long, value_name = "PATH")]
+    errors: Option<PathBuf>,
+
+    /// Format of the rejection report [default: jsonl for .jsonl files, otherwise csv]
+    #[arg(long, value_enum, value_name = "FORMAT", requires = "errors")]
+    errors_format: Option<ReportFormat>,
+
+    /// Does not report rejected events on standard error
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also reports progress on standard error; repeat for processing decisions (-vv) and
+    /// everything else (-vvv)
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of the log written to standard error
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Prints statistics about the run on standard error once it is complete
+    #[arg(long)]
+    summary: bool,
+
+    /// Writes statistics about the run to PATH as JSON
+    #[arg(long, value_name = "PATH")]
+    summary_json: Option<PathBuf>,
+
+    /// Allows withdrawals to be disputed, holding the withdrawn amount until the dispute closes
+    #[arg(long)]
+    dispute_withdrawals: bool,
+
+    /// Rejects disputes more than SECONDS after their transaction, for timestamped events
+    #[arg(long, value_name = "SECONDS")]
+    dispute_window: Option<u64>,
+
+    /// Allows transactions whose dispute was resolved to be disputed again
+    #[arg(long)]
+    allow_redisputes: bool,
+
+    /// Reads the exchange rates for convert events from the CSV file at PATH, with from, to and
+    /// rate columns
+    #[arg(long, value_name = "PATH")]
+    rates: Option<PathBuf>,
+
+    /// How converted amounts and proportional fees are rounded to four decimal places
+    #[arg(long, value_enum, default_value_t = Rounding::HalfEven)]
+    rounding: Rounding,
+
+    /// Reads the fees charged on deposits and withdrawals from the CSV file at PATH, with type,
+    /// client, from, flat and rate columns
+    #[arg(long, value_name = "PATH", requires = "house_account")]
+    fees: Option<PathBuf>,
+
+    /// Client credited with every fee; required with --fees
+    #[arg(long, value_name = "ID")]
+    house_account: Option<u16>,
+
+    /// Reads the deposit and withdrawal limits from the CSV file at PATH, with client, currency,
+    /// max_deposit, max_withdrawal and max_daily_withdrawal columns
+    #[arg(long, value_name = "PATH")]
+    limits: Option<PathBuf>,
+
+    /// Appends the state changes made by every applied event to the journal at PATH
+    #[arg(short, long, value_name = "PATH")]
+    journal: Option<PathBuf>,
+
+    /// Starts from the accounts and transactions in the snapshot at PATH
+    #[arg(long, value_name = "PATH")]
+    snapshot_in: Option<PathBuf>,
+
+    /// Writes a snapshot of the final accounts and transactions to PATH
+    #[arg(long, value_name = "PATH")]
+    snapshot_out: Option<PathBuf>,
+
+    /// Processes the events of different clients concurrently on N workers
+    #[arg(long, value_name = "N")]
+    workers: Option<NonZeroUsize>,
+
+    /// Processes events against the SQLite database at PATH, creating it if needed
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH", conflicts_with = "workers")]
+    database: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct HistoryArgs {
+    /// Journal written by `process --journal`
+    #[arg(value_name = "JOURNAL")]
+    journal: PathBuf,
+
+    /// Client whose history to print
+    #[arg(short, long)]
+    client: u16,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Journal written by `process --journal`
+    #[arg(value_name = "JOURNAL")]
+    journal: PathBuf,
+
+    /// Writes the client report to PATH instead of standard output
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Format of the client report [default: json for .json files, jsonl for .jsonl files,
+    /// otherwise csv]
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Order of the client report; ties are broken by client ID
+    #[arg(long, value_enum, default_value_t = SortOrder::Id)]
+    sort: SortOrder,
+
+    /// Rebuilds the balances into a new SQLite database at PATH
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH")]
+    database: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TransactionsArgs {
+    #[command(flatten)]
+    source: LedgerSource,
+
+    /// Only prints the transactions of this client
+    #[arg(short, long)]
+    client: Option<u16>,
+
+    /// Writes the ledger to PATH instead of standard output
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Format of the ledger [default: json for .json files, jsonl for .jsonl files, otherwise
+    /// csv]
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+}
+
+/// Where the `transactions` subcommand reads the ledger from; exactly one is required.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct LedgerSource {
+    /// Journal written by `process --journal`
+    #[arg(value_name = "JOURNAL")]
+    journal: Option<PathBuf>,
+
+    /// Reads the ledger from the snapshot at PATH, written by `process --snapshot-out`
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+
+    /// Reads the ledger from the SQLite database at PATH, written by `process --database`
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH")]
+    database: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EventFormat {
+    Csv,
+    Jsonl,
+}
+
+impl From<EventFormat> for InputFormat {
+    fn from(format: EventFormat) -> Self {
+        match format {
+            EventFormat::Csv => InputFormat::Csv,
+            EventFormat::Jsonl => InputFormat::JsonLines,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    /// A single JSON array
+    Json,
+    Jsonl,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Csv => OutputFormat::Csv,
+            Format::Json => OutputFormat::Json,
+            Format::Jsonl => OutputFormat::JsonLines,
+        }
+    }
+}
+
+/// The format set explicitly, else the one implied by the output's extension, else CSV.
+fn output_format(format: Option<Format>, path: &Option<PathBuf>) -> OutputFormat {
+    match (format, path) {
+        (Some(format), _) => format.into(),
+        (None, Some(path)) => OutputFormat::from_extension(path).unwrap_or_default(),
+        (None, None) => OutputFormat::default(),
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortOrder {
+    /// Ascending client ID
+    Id,
+    /// Descending total balance
+    Total,
+    /// Locked accounts first
+    Locked,
+}
+
+impl From<SortOrder> for ClientOrder {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Id => ClientOrder::Id,
+            SortOrder::Total => ClientOrder::Total,
+            SortOrder::Locked => ClientOrder::Locked,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Rounding {
+    /// To the nearest amount, ties to the even one
+    HalfEven,
+    /// To the nearest amount, ties away from zero
+    HalfUp,
+    /// Towards zero
+    Down,
+    /// Away from zero
+    Up,
+}
+
+impl From<Rounding> for RoundingMode {
+    fn from(rounding: Rounding) -> Self {
+        match rounding {
+            Rounding::HalfEven => RoundingMode::HalfEven,
+            Rounding::HalfUp => RoundingMode::HalfUp,
+            Rounding::Down => RoundingMode::Down,
+            Rounding::Up => RoundingMode::Up,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Jsonl,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ReportFormat {
+    fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("jsonl") | Some("ndjson") => ReportFormat::Jsonl,
+            _ => ReportFormat::Csv,
+        }
+    }
+}
+
+impl ProcessArgs {
+    fn log_level(&self) -> Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => Level::Error,
+            (false, 0) => Level::Warn,
+            (false, 1) => Level::Info,
+            (false, 2) => Level::Debug,
+            (false, _) => Level::Trace,
+        }
+    }
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
-    let input_file = args[1].clone(); // low-effort, no-validation argument "parsing"
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Process(args) => process(*args).await.map(|summary| summary_code(&summary)),
+        Command::History(args) => history(args).map(|()| 0),
+        Command::Replay(args) => replay(args).map(|()| 0),
+        Command::Transactions(args) => transactions(args).map(|()| 0),
+    };
+    let code = match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            error_code(&e)
+        }
+    };
+    process::exit(code);
+}
+
+fn summary_code(summary: &RunSummary) -> i32 {
+    if summary.parse_failures > 0 {
+        EXIT_PARSE_FAILURE
+    } else if summary.rejected > 0 {
+        EXIT_REJECTED
+    } else {
+        0
+    }
+}
+
+fn error_code(e: &RunnerError) -> i32 {
+    match e {
+        RunnerError::FileError { .. }
+        | RunnerError::InputError { .. }
+        | RunnerError::OutputError(_)
+        | RunnerError::JournalError(JournalError::Io(_))
+        | RunnerError::SnapshotError(SnapshotError::Io(_))
+        | RunnerError::RatesError(RatesError::Io(_))
+        | RunnerError::FeesError(FeesError::Io(_))
+        | RunnerError::LimitsError(LimitsError::Io(_)) => EXIT_IO_FAILURE,
+        RunnerError::StoreError(_)
+        | RunnerError::JournalError(_)
+        | RunnerError::SnapshotError(_)
+        | RunnerError::RatesError(_)
+        | RunnerError::FeesError(_)
+        | RunnerError::LimitsError(_)
+        | RunnerError::WorkerError(_) => EXIT_FAILURE,
+    }
+}
 
-    if let Err(e) = transaction_processor::process_events_from_file(&input_file).await {
-        eprintln!("{}", e);
-        std::process::exit(1);
+fn output(path: &Option<PathBuf>) -> io::Result<Box<dyn Write + Send>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+async fn process(args: ProcessArgs) -> Result<RunSummary, RunnerError> {
+    let level = args.log_level();
+    match args.log_format {
+        LogFormat::Text => process_with_logger(&args, StderrLogger::new(level)).await,
+        LogFormat::Jsonl => {
+            process_with_logger(&args, JsonLinesLogger::new(io::stderr(), level)).await
+        }
     }
 }
+
+async fn process_with_logger<L: Logger + Default + Send + Sync + 'static>(
+    args: &ProcessArgs,
+    logger: L,
+) -> Result<RunSummary, RunnerError> {
+    let config = run_config(args)?;
+    let summary = run(args, config, logger).await?;
+    if args.summary {
+        print_summary(&summary);
+    }
+    if let Some(path) = &args.summary_json {
+        let mut output = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut output, &summary).map_err(io::Error::from)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok(summary)
+}
+
+/// Runs `config` against the store and with the runner the arguments ask for.
+async fn run<L: Logger + Default + Send + Sync + 'static>(
+    args: &ProcessArgs,
+    config: RunConfig,
+    logger: L,
+) -> Result<RunSummary, RunnerError> {
+    #[cfg(feature = "sqlite")]
+    if let Some(database) = &args.database {
+        let store = transaction_processor::store::SqliteStore::open(database)?;
+        return CsvSingleProcessRunner::with_config(config, store)
+            .with_logger(logger)
+            .run()
+            .await;
+    }
+    match args.workers {
+        Some(workers) => {
+            ShardedRunner::with_config(config, workers.get(), ConcurrentStore::default())
+                .with_logger(logger)
+                .run()
+                .await
+        }
+        None => {
+            CsvSingleProcessRunner::with_config(config, InMemoryStore::default())
+                .with_logger(logger)
+                .run()
+                .await
+        }
+    }
+}
+
+fn run_config(args: &ProcessArgs) -> Result<RunConfig, RunnerError> {
+    let (first, rest) = args
+        .inputs
+        .split_first()
+        .expect("at least one input is required");
+    let rates = match &args.rates {
+        Some(path) => RateTable::load(path)?,
+        None => RateTable::default(),
+    };
+    let fees = match &args.fees {
+        Some(path) => FeeSchedule::load(path)?,
+        None => FeeSchedule::default(),
+    };
+    let limits = match &args.limits {
+        Some(path) => LimitTable::load(path)?,
+        None => LimitTable::default(),
+    };
+    let policy = Policy::default()
+        .with_disputable_withdrawals(args.dispute_withdrawals)
+        .with_dispute_window(args.dispute_window.map(Duration::from_secs))
+        .with_redisputes(args.allow_redisputes)
+        .with_rates(rates)
+        .with_rounding(args.rounding.into())
+        .with_fees(fees)
+        .with_house_account(args.house_account.unwrap_or_default())
+        .with_limits(limits);
+    let input = |name: &String| {
+        let input = Input::from(name.as_str());
+        match args.input_format {
+            Some(format) => input.with_format(format.into()),
+            None => input,
+        }
+    };
+    let mut config = RunConfig::new(input(first))
+        .with_policy(policy)
+        .with_client_order(args.sort.into())
+        .with_output(output(&args.output)?)
+        .with_output_format(output_format(args.format, &args.output));
+    for name in rest {
+        config = config.add_input(input(name));
+    }
+    if let Some(path) = &args.errors {
+        let output = BufWriter::new(File::create(path)?);
+        config = match args
+            .errors_format
+            .unwrap_or_else(|| ReportFormat::from_extension(path))
+        {
+            ReportFormat::Csv => config.with_rejections(CsvRejectionSink::new(output)),
+            ReportFormat::Jsonl => config.with_rejections(JsonLinesRejectionSink::new(output)),
+        };
+    }
+    if let Some(path) = &args.transactions {
+        let format = OutputFormat::from_extension(path).unwrap_or_default();
+        config = config.with_transaction_output(BufWriter::new(File::create(path)?), format);
+    }
+    if let Some(path) = &args.journal {
+        config = config.with_journal(FileJournal::open(path)?);
+    }
+    if let Some(path) = &args.snapshot_in {
+        config = config.with_snapshot(Snapshot::load(path)?);
+    }
+    if let Some(path) = &args.snapshot_out {
+        config = config.with_snapshot_output(BufWriter::new(File::create(path)?));
+    }
+    Ok(config)
+}
+
+fn print_summary(summary: &RunSummary) {
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "none".to_owned()
+        } else {
+            items.join(", ")
+        }
+    };
+    let totals = |totals: &BTreeMap<Currency, Amount>| {
+        list(
+            totals
+                .iter()
+                .map(|(currency, amount)| format!("{} {}", amount, currency))
+                .collect(),
+        )
+    };
+    eprintln!(
+        "Rows read:        {} in {:.3}s ({:.0} rows/s)",
+        summary.rows_read,
+        summary.elapsed.as_secs_f64(),
+        summary.rows_per_second
+    );
+    eprintln!("Applied:          {}", summary.applied);
+    eprintln!("Rejected:         {}", summary.rejected);
+    eprintln!("Parse failures:   {}", summary.parse_failures);
+    eprintln!(
+        "Events:           {}",
+        list(
+            summary
+                .events
+                .iter()
+                .map(|(event_type, count)| format!("{} {}", event_type, count))
+                .collect()
+        )
+    );
+    eprintln!(
+        "Rejections:       {}",
+        list(
+            summary
+                .rejections
+                .iter()
+                .map(|(code, count)| format!("{} {}", code, count))
+                .collect()
+        )
+    );
+    eprintln!("Deposited:        {}", totals(&summary.deposited));
+    eprintln!("Withdrawn:        {}", totals(&summary.withdrawn));
+    eprintln!("Transferred:      {}", totals(&summary.transferred));
+    eprintln!("Charged back:     {}", totals(&summary.charged_back));
+    eprintln!("Fees:             {}", totals(&summary.fees));
+    eprintln!("Locked accounts:  {}", summary.locked_accounts);
+}
+
+fn history(args: HistoryArgs) -> Result<(), RunnerError> {
+    let mut output = BufWriter::new(io::stdout());
+    for entry in FileJournal::read(&args.journal)? {
+        if entry.affects_client(args.client) {
+            serde_json::to_writer(&mut output, &entry).map_err(io::Error::from)?;
+            output.write_all(b"\n")?;
+        }
+    }
+    Ok(output.flush()?)
+}
+
+fn replay(args: ReplayArgs) -> Result<(), RunnerError> {
+    let entries = FileJournal::read(&args.journal)?;
+    #[cfg(feature = "sqlite")]
+    if let Some(database) = &args.database {
+        let store = transaction_processor::store::SqliteStore::open(database)?;
+        return replay_into(&entries, store, &args);
+    }
+    replay_into(&entries, InMemoryStore::default(), &args)
+}
+
+fn replay_into<S: Store>(
+    entries: &[JournalEntry],
+    mut store: S,
+    args: &ReplayArgs,
+) -> Result<(), RunnerError> {
+    journal::replay(entries, &mut store)?;
+    runner::write_clients(
+        &store,
+        output(&args.output)?,
+        output_format(args.format, &args.output),
+        args.sort.into(),
+        &StderrLogger::default(),
+    )
+}
+
+fn transactions(args: TransactionsArgs) -> Result<(), RunnerError> {
+    #[cfg(feature = "sqlite")]
+    if let Some(database) = &args.source.database {
+        // Opening would otherwise create an empty database in place of a mistyped one
+        if !database.exists() {
+            return Err(RunnerError::FileError {
+                path: database.clone(),
+                source: io::ErrorKind::NotFound.into(),
+            });
+        }
+        let store = transaction_processor::store::SqliteStore::open(database)?;
+        return write_ledger(&store, &args);
+    }
+    let mut store = InMemoryStore::default();
+    if let Some(path) = &args.source.snapshot {
+        Snapshot::load(path)?.restore(&mut store)?;
+    }
+    if let Some(path) = &args.source.journal {
+        journal::replay(&FileJournal::read(path)?, &mut store)?;
+    }
+    write_ledger(&store, &args)
+}
+
+fn write_ledger<S: Store>(store: &S, args: &TransactionsArgs) -> Result<(), RunnerError> {
+    runner::write_transactions(
+        store,
+        output(&args.output)?,
+        output_format(args.format, &args.output),
+        args.client,
+    )
+}
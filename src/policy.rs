@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use crate::event::Timestamp;
+use crate::fees::FeeSchedule;
+use crate::limits::LimitTable;
+use crate::rates::{RateTable, RoundingMode};
+
+/// Business rules which vary between deployments, passed to every processor.
+///
+/// The default policy matches the behaviour described in the README.
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct Policy {
+    /// Whether withdrawals can be disputed.
+    ///
+    /// A disputed withdrawal holds the withdrawn amount on the credit side: `held` increases
+    /// while `available` is unchanged, as the funds may be coming back. Resolving the dispute
+    /// releases the hold and the withdrawal stands; a chargeback returns the funds to
+    /// `available` and locks the account, as for deposits.
+    pub disputable_withdrawals: bool,
+    /// How long after a transaction it can still be disputed, if limited.
+    ///
+    /// Only enforced when both the transaction and the dispute have a timestamp.
+    pub dispute_window: Option<Duration>,
+    /// Whether a transaction whose dispute was resolved can be disputed again.
+    ///
+    /// Charged back transactions can never be disputed again.
+    pub allow_redisputes: bool,
+    /// Exchange rates for `convert` events; a conversion without a rate is rejected.
+    pub rates: RateTable,
+    /// How converted amounts and proportional fees are rounded to four decimal places.
+    pub rounding: RoundingMode,
+    /// Fees charged on deposits and withdrawals; none by default.
+    pub fees: FeeSchedule,
+    /// The client credited with every fee.
+    pub house_account: u16,
+    /// Deposit and withdrawal limits; none by default.
+    pub limits: LimitTable,
+}
+
+impl Policy {
+    pub fn with_disputable_withdrawals(mut self, disputable_withdrawals: bool) -> Self {
+        self.disputable_withdrawals = disputable_withdrawals;
+        self
+    }
+
+    pub fn with_dispute_window(mut self, dispute_window: Option<Duration>) -> Self {
+        self.dispute_window = dispute_window;
+        self
+    }
+
+    pub fn with_redisputes(mut self, allow_redisputes: bool) -> Self {
+        self.allow_redisputes = allow_redisputes;
+        self
+    }
+
+    pub fn with_rates(mut self, rates: RateTable) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn with_house_account(mut self, house_account: u16) -> Self {
+        self.house_account = house_account;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LimitTable) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether a transaction created at `created_at` can be disputed at `disputed_at`.
+    pub fn within_dispute_window(
+        &self,
+        created_at: Option<Timestamp>,
+        disputed_at: Option<Timestamp>,
+    ) -> bool {
+        match (self.dispute_window, created_at, disputed_at) {
+            (Some(window), Some(created_at), Some(disputed_at)) => {
+                Duration::from_secs(disputed_at.saturating_sub(created_at)) <= window
+            }
+            _ => true,
+        }
+    }
+}
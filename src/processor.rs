@@ -1,5 +1,14 @@
-use crate::runner::Event;
-use crate::store::{Client, StoreError, Transaction};
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::event::{
+    AccountEvent, AdjustmentEvent, ConvertEvent, EventType, FundsEvent, ReferenceEvent,
+    TransferEvent,
+};
+use crate::logger::{Fields, Logger};
+use crate::policy::Policy;
+use crate::store::{
+    Client, DailyTotal, DisputeState, Fee, StoreError, Transaction, TransactionKind,
+};
 use async_trait::async_trait;
 use thiserror::Error;
 
@@ -9,8 +18,6 @@ pub enum ProcessorError {
     StoreError(#[from] StoreError),
     #[error("Attempted processing of transaction that has already been processed")]
     TransactionExists,
-    #[error("Amount not specified for transaction")]
-    NoAmount,
     #[error("Attempted to deposit or withdraw on locked client account")]
     ClientLocked,
     #[error("Withdrawal exceeds client withdrawable (free) balance")]
@@ -27,76 +34,256 @@ pub enum ProcessorError {
     WithdrawalNotDisputable,
     #[error("Attempted to close a dispute on a non-disputed transaction")]
     TransactionNotDisputed,
+    #[error("Attempted to dispute a transaction whose dispute was already resolved")]
+    RedisputeNotAllowed,
+    #[error("Attempted to dispute a transaction which was charged back")]
+    TransactionChargedBack,
+    #[error("Transaction amount overflows client balance")]
+    AmountOverflow,
+    #[error("Attempted to open dispute after the dispute window closed")]
+    DisputeWindowExpired,
+    #[error("Event timestamp is earlier than a previous event for the client")]
+    TimestampOutOfOrder,
+    #[error("Attempted to open dispute on a transaction which is not a deposit or withdrawal")]
+    TransactionNotDisputable,
+    #[error("Attempted to lock an already locked client account")]
+    ClientAlreadyLocked,
+    #[error("Attempted to unlock a client account which is not locked")]
+    ClientNotLocked,
+    #[error("Adjustment exceeds client available balance")]
+    AdjustmentAboveBalance,
+    #[error("No exchange rate from the source to the target currency of the conversion")]
+    RateMissing,
+    #[error("Conversion exceeds client available balance in the source currency")]
+    ConversionAboveBalance,
+    #[error("Converted amount rounds to zero in the target currency")]
+    ConversionRoundsToZero,
+    #[error("Attempted to transfer funds from a client account to itself")]
+    TransferToSelf,
+    #[error("Transfer refers to nonexistent destination client")]
+    DestinationMissing,
+    #[error("Attempted to transfer funds to locked client account")]
+    DestinationLocked,
+    #[error("Transfer exceeds source client available balance")]
+    TransferAboveBalance,
+    #[error("Deposit fee exceeds the deposited amount")]
+    FeeAboveDeposit,
+    #[error("Deposit exceeds the client's maximum deposit")]
+    DepositLimitExceeded,
+    #[error("Withdrawal exceeds the client's maximum withdrawal")]
+    WithdrawalLimitExceeded,
+    #[error("Withdrawal exceeds the client's maximum daily withdrawal total")]
+    DailyWithdrawalLimitExceeded,
+    #[error("Withdrawal without a timestamp is subject to a daily withdrawal limit")]
+    DailyLimitWithoutTimestamp,
+}
+
+impl ProcessorError {
+    /// Stable, machine-readable identifier of the error variant, e.g. for rejection reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessorError::StoreError(_) => "store_error",
+            ProcessorError::TransactionExists => "transaction_exists",
+            ProcessorError::ClientLocked => "client_locked",
+            ProcessorError::WithdrawalAboveBalance => "withdrawal_above_balance",
+            ProcessorError::ClientMissing => "client_missing",
+            ProcessorError::ClientTransactionMismatch => "client_transaction_mismatch",
+            ProcessorError::TransactionMissing => "transaction_missing",
+            ProcessorError::TransactionDisputed => "transaction_disputed",
+            ProcessorError::WithdrawalNotDisputable => "withdrawal_not_disputable",
+            ProcessorError::TransactionNotDisputed => "transaction_not_disputed",
+            ProcessorError::RedisputeNotAllowed => "redispute_not_allowed",
+            ProcessorError::TransactionChargedBack => "transaction_charged_back",
+            ProcessorError::AmountOverflow => "amount_overflow",
+            ProcessorError::DisputeWindowExpired => "dispute_window_expired",
+            ProcessorError::TimestampOutOfOrder => "timestamp_out_of_order",
+            ProcessorError::TransactionNotDisputable => "transaction_not_disputable",
+            ProcessorError::ClientAlreadyLocked => "client_already_locked",
+            ProcessorError::ClientNotLocked => "client_not_locked",
+            ProcessorError::AdjustmentAboveBalance => "adjustment_above_balance",
+            ProcessorError::RateMissing => "rate_missing",
+            ProcessorError::ConversionAboveBalance => "conversion_above_balance",
+            ProcessorError::ConversionRoundsToZero => "conversion_rounds_to_zero",
+            ProcessorError::TransferToSelf => "transfer_to_self",
+            ProcessorError::DestinationMissing => "destination_missing",
+            ProcessorError::DestinationLocked => "destination_locked",
+            ProcessorError::TransferAboveBalance => "transfer_above_balance",
+            ProcessorError::FeeAboveDeposit => "fee_above_deposit",
+            ProcessorError::DepositLimitExceeded => "deposit_limit_exceeded",
+            ProcessorError::WithdrawalLimitExceeded => "withdrawal_limit_exceeded",
+            ProcessorError::DailyWithdrawalLimitExceeded => "daily_withdrawal_limit_exceeded",
+            ProcessorError::DailyLimitWithoutTimestamp => "daily_limit_without_timestamp",
+        }
+    }
 }
 
 #[async_trait]
 pub trait Processor {
+    type Event: Sync;
+
+    /// Applies `event` under the rules in `policy`.
+    ///
+    /// Decisions are reported to `logger` at debug level; rejections are left to the caller.
     async fn process_event(
         maybe_tx: Option<Transaction>,
         maybe_client: Option<Client>,
-        event: &Event,
+        event: &Self::Event,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
     ) -> Result<(Client, Transaction), ProcessorError>;
 }
 
+/// Processes an event which changes the accounts of two clients at once: the event's client and
+/// a counterparty, the destination of a transfer.
+///
+/// Both clients are returned, and must be stored together with the transaction.
+#[async_trait]
+pub trait CounterpartyProcessor {
+    type Event: Sync;
+
+    /// Applies `event` under the rules in `policy`.
+    ///
+    /// Decisions are reported to `logger` at debug level; rejections are left to the caller.
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        maybe_counterparty: Option<Client>,
+        event: &Self::Event,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Client, Transaction), ProcessorError>;
+}
+
+fn checked(amount: Option<Amount>) -> Result<Amount, ProcessorError> {
+    amount.ok_or(ProcessorError::AmountOverflow)
+}
+
+/// The fee the policy charges `client` on an event for `amount`, payable to its house account.
+fn charged_fee(
+    policy: &Policy,
+    client: u16,
+    event_type: EventType,
+    amount: Amount,
+) -> Result<Option<Fee>, ProcessorError> {
+    let fee = checked(policy.fees.fee(client, event_type, amount, policy.rounding))?;
+    Ok(if fee == Amount::ZERO {
+        None
+    } else {
+        Some(Fee {
+            account: policy.house_account,
+            amount: fee,
+        })
+    })
+}
+
+/// The amount of a fee, which is zero if there is none.
+fn fee_amount(fee: Option<Fee>) -> Amount {
+    fee.map_or(Amount::ZERO, |fee| fee.amount)
+}
+
+fn fields(client: u16, tx: u32) -> Fields {
+    Fields {
+        client: Some(client),
+        tx: Some(tx),
+        ..Fields::default()
+    }
+}
+
 pub struct DepositProcessor;
 pub struct WithdrawalProcessor;
 pub struct DisputeProcessor;
 pub struct ResolveProcessor;
 pub struct ChargebackProcessor;
+pub struct LockProcessor;
+pub struct UnlockProcessor;
+pub struct AdjustmentProcessor;
+pub struct ConvertProcessor;
+pub struct TransferProcessor;
+pub struct TransferDisputeProcessor;
+pub struct TransferResolveProcessor;
+pub struct TransferChargebackProcessor;
 
 #[async_trait]
 impl Processor for DepositProcessor {
+    type Event = FundsEvent;
+
     async fn process_event(
         maybe_tx: Option<Transaction>,
         maybe_client: Option<Client>,
-        event: &Event,
+        event: &FundsEvent,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
     ) -> Result<(Client, Transaction), ProcessorError> {
         // PRECONDITION: transaction must be unique
-        if let Some(_) = maybe_tx {
+        if maybe_tx.is_some() {
             return Err(ProcessorError::TransactionExists);
         }
         // PRECONDITION: client must not be locked
         match maybe_client {
             Some(client) if client.locked => return Err(ProcessorError::ClientLocked),
-            _ => ()
-        };
-        // PRECONDITION: event must have an amount
-        let amount = match event.amount {
-            None => return Err(ProcessorError::NoAmount),
-            Some(amount) => amount,
+            _ => (),
         };
+        let amount = event.amount;
+        // PRECONDITION: deposit must be within the client's limit
+        let limits = policy.limits.get(event.client, event.currency);
+        if limits.max_deposit.is_some_and(|max| amount > max) {
+            return Err(ProcessorError::DepositLimitExceeded);
+        }
+        // PRECONDITION: any fee must not exceed the deposit
+        let fee = charged_fee(policy, event.client, EventType::Deposit, amount)?;
+        if fee_amount(fee) > amount {
+            return Err(ProcessorError::FeeAboveDeposit);
+        }
         // OK
-        // POSTCONDITION: client saved with new value (or inserted if did not exist)
-        let mut client = maybe_client.unwrap_or(Client::default());
+        // POSTCONDITION: client saved with new value (or inserted if did not exist), less the fee
+        let mut client = maybe_client.unwrap_or_default();
         client.id = event.client; // in case it was a new client
-        client.available += amount;
+        let balance = client.balance_mut(event.currency);
+        let credit = checked(amount.checked_sub(fee_amount(fee)))?;
+        balance.available = checked(balance.available.checked_add(credit))?;
+        let available = balance.available;
         // POSTCONDITION: new transaction created
         let tx = Transaction {
             id: event.tx,
             client: event.client,
+            kind: TransactionKind::Deposit,
             amount,
-            disputed: false,
+            state: DisputeState::Settled,
+            currency: event.currency,
+            counterparty: None,
+            fee,
+            created_at: event.timestamp,
         };
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Deposited {} {} less a fee of {}, available now {}",
+                amount,
+                event.currency,
+                fee_amount(fee),
+                available
+            ),
+        );
         Ok((client, tx))
     }
 }
 
 #[async_trait]
 impl Processor for WithdrawalProcessor {
+    type Event = FundsEvent;
+
     async fn process_event(
         maybe_tx: Option<Transaction>,
         maybe_client: Option<Client>,
-        event: &Event,
+        event: &FundsEvent,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
     ) -> Result<(Client, Transaction), ProcessorError> {
         // PRECONDITION: transaction must be unique
-        if let Some(_) = maybe_tx {
+        if maybe_tx.is_some() {
             return Err(ProcessorError::TransactionExists);
         }
-        // PRECONDITION: event must have an amount
-        let amount = match event.amount {
-            None => return Err(ProcessorError::NoAmount),
-            Some(amount) => amount,
-        };
+        let amount = event.amount;
         // PRECONDITION: client must exist
         let mut client = match maybe_client {
             None => return Err(ProcessorError::ClientMissing),
@@ -106,43 +293,104 @@ impl Processor for WithdrawalProcessor {
         if client.locked {
             return Err(ProcessorError::ClientLocked);
         }
-        // PRECONDITION: withdrawal must not exceed client balance
-        if amount > client.available {
+        // PRECONDITION: withdrawal must be within the client's limits, the daily one counting
+        // the amount withdrawn but not fees
+        let limits = policy.limits.get(event.client, event.currency);
+        if limits.max_withdrawal.is_some_and(|max| amount > max) {
+            return Err(ProcessorError::WithdrawalLimitExceeded);
+        }
+        let withdrawn_today = match limits.max_daily_withdrawal {
+            Some(max) => {
+                // PRECONDITION: withdrawal must have a timestamp to find its day
+                let timestamp = event
+                    .timestamp
+                    .ok_or(ProcessorError::DailyLimitWithoutTimestamp)?;
+                let previous = client.withdrawn_today.get(&event.currency).copied();
+                let total = DailyTotal::add(previous, timestamp, amount)
+                    .ok_or(ProcessorError::AmountOverflow)?;
+                if total.amount > max {
+                    return Err(ProcessorError::DailyWithdrawalLimitExceeded);
+                }
+                Some(total)
+            }
+            None => None,
+        };
+        // PRECONDITION: withdrawal and any fee must not exceed client balance in its currency
+        let fee = charged_fee(policy, event.client, EventType::Withdrawal, amount)?;
+        let debit = checked(amount.checked_add(fee_amount(fee)))?;
+        if debit > client.balance(event.currency).available {
             return Err(ProcessorError::WithdrawalAboveBalance);
         }
         // OK
-        // POSTCONDITION: client available balanced reduced
-        client.available -= amount;
+        // POSTCONDITION: client available balanced reduced by the amount and the fee
+        let balance = client.balance_mut(event.currency);
+        balance.available = checked(balance.available.checked_sub(debit))?;
+        let available = balance.available;
+        // POSTCONDITION: daily total kept while a daily limit applies
+        match withdrawn_today {
+            Some(total) => client.withdrawn_today.insert(event.currency, total),
+            None => client.withdrawn_today.remove(&event.currency),
+        };
         // POSTCONDITION: new transaction created
         let tx = Transaction {
             id: event.tx,
             client: event.client,
-            amount: amount * -1f64,
-            disputed: false,
+            kind: TransactionKind::Withdrawal,
+            amount: checked(amount.checked_neg())?,
+            state: DisputeState::Settled,
+            currency: event.currency,
+            counterparty: None,
+            fee,
+            created_at: event.timestamp,
         };
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Withdrew {} {} plus a fee of {}, available now {}",
+                amount,
+                event.currency,
+                fee_amount(fee),
+                available
+            ),
+        );
         Ok((client, tx))
     }
 }
 
 #[async_trait]
 impl Processor for DisputeProcessor {
+    type Event = ReferenceEvent;
+
     async fn process_event(
         maybe_tx: Option<Transaction>,
         maybe_client: Option<Client>,
-        _event: &Event,
+        event: &ReferenceEvent,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
     ) -> Result<(Client, Transaction), ProcessorError> {
         // PRECONDITION: transaction must exist
         let mut tx = match maybe_tx {
             None => return Err(ProcessorError::TransactionMissing),
             Some(tx) => tx,
         };
-        // PRECONDITION: transaction must not be under dispute
-        if tx.disputed {
-            return Err(ProcessorError::TransactionDisputed);
+        // PRECONDITION: transaction must be open to dispute
+        match tx.state {
+            DisputeState::Settled => (),
+            DisputeState::Resolved if policy.allow_redisputes => (),
+            DisputeState::Resolved => return Err(ProcessorError::RedisputeNotAllowed),
+            DisputeState::Disputed => return Err(ProcessorError::TransactionDisputed),
+            DisputeState::ChargedBack => return Err(ProcessorError::TransactionChargedBack),
         }
-        // PRECONDITION: transaction must not have been a withdrawal
-        if tx.amount < 0f64 {
-            return Err(ProcessorError::WithdrawalNotDisputable);
+        // PRECONDITION: transaction must have been a deposit, or a withdrawal if the policy allows
+        match tx.kind {
+            TransactionKind::Deposit => (),
+            TransactionKind::Withdrawal if policy.disputable_withdrawals => (),
+            TransactionKind::Withdrawal => return Err(ProcessorError::WithdrawalNotDisputable),
+            _ => return Err(ProcessorError::TransactionNotDisputable),
+        }
+        // PRECONDITION: dispute must be within the policy's window, where both times are known
+        if !policy.within_dispute_window(tx.created_at, event.timestamp) {
+            return Err(ProcessorError::DisputeWindowExpired);
         }
         // PRECONDITION: client must exist
         let mut client = match maybe_client {
@@ -154,21 +402,38 @@ impl Processor for DisputeProcessor {
             return Err(ProcessorError::ClientTransactionMismatch);
         }
         // OK
-        // POSTCONDITION: client funds are held, to maximum extent
-        client.available -= tx.amount;
-        client.held += tx.amount;
+        // POSTCONDITION: funds move in the currency of the disputed transaction, net of any
+        // deposit fee
+        let amount = checked(tx.disputed_amount())?;
+        let balance = client.balance_mut(tx.currency);
+        if amount.is_negative() {
+            // POSTCONDITION: withdrawn funds are held on the credit side, pending their return
+            balance.held = checked(balance.held.checked_sub(amount))?;
+        } else {
+            // POSTCONDITION: client funds are held, to maximum extent
+            balance.available = checked(balance.available.checked_sub(amount))?;
+            balance.held = checked(balance.held.checked_add(amount))?;
+        }
         // POSTCONDITION: transaction is marked as currently disputed
-        tx.disputed = true;
+        tx.state = DisputeState::Disputed;
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!("Disputed transaction, holding {}", amount),
+        );
         Ok((client, tx))
     }
 }
 
 #[async_trait]
 impl Processor for ResolveProcessor {
+    type Event = ReferenceEvent;
+
     async fn process_event(
         maybe_tx: Option<Transaction>,
         maybe_client: Option<Client>,
-        _event: &Event,
+        event: &ReferenceEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
     ) -> Result<(Client, Transaction), ProcessorError> {
         // PRECONDITION: transaction must exist
         let mut tx = match maybe_tx {
@@ -176,7 +441,7 @@ impl Processor for ResolveProcessor {
             Some(tx) => tx,
         };
         // PRECONDITION: transaction must be under dispute
-        if !tx.disputed {
+        if tx.state != DisputeState::Disputed {
             return Err(ProcessorError::TransactionNotDisputed);
         }
         // PRECONDITION: client must exist
@@ -189,21 +454,36 @@ impl Processor for ResolveProcessor {
             return Err(ProcessorError::ClientTransactionMismatch);
         }
         // OK
-        // POSTCONDITION: client held funds from the dispute are released
-        client.available += tx.amount;
-        client.held -= tx.amount;
-        // POSTCONDITION: transaction is no longer under dispute
-        tx.disputed = false;
+        let amount = checked(tx.disputed_amount())?;
+        let balance = client.balance_mut(tx.currency);
+        if amount.is_negative() {
+            // POSTCONDITION: held funds are released and the withdrawal stands
+            balance.held = checked(balance.held.checked_add(amount))?;
+        } else {
+            // POSTCONDITION: client held funds from the dispute are released
+            balance.available = checked(balance.available.checked_add(amount))?;
+            balance.held = checked(balance.held.checked_sub(amount))?;
+        }
+        // POSTCONDITION: transaction's dispute is resolved
+        tx.state = DisputeState::Resolved;
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!("Resolved dispute, releasing {}", amount),
+        );
         Ok((client, tx))
     }
 }
 
 #[async_trait]
 impl Processor for ChargebackProcessor {
+    type Event = ReferenceEvent;
+
     async fn process_event(
         maybe_tx: Option<Transaction>,
         maybe_client: Option<Client>,
-        _event: &Event,
+        event: &ReferenceEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
     ) -> Result<(Client, Transaction), ProcessorError> {
         // PRECONDITION: transaction must exist
         let mut tx = match maybe_tx {
@@ -211,7 +491,7 @@ impl Processor for ChargebackProcessor {
             Some(tx) => tx,
         };
         // PRECONDITION: transaction must be under dispute
-        if !tx.disputed {
+        if tx.state != DisputeState::Disputed {
             return Err(ProcessorError::TransactionNotDisputed);
         }
         // PRECONDITION: client must exist
@@ -224,31 +504,492 @@ impl Processor for ChargebackProcessor {
             return Err(ProcessorError::ClientTransactionMismatch);
         }
         // OK
-        // POSTCONDITION: client held funds are removed from the client
-        client.held -= tx.amount;
+        let amount = checked(tx.disputed_amount())?;
+        let balance = client.balance_mut(tx.currency);
+        if amount.is_negative() {
+            // POSTCONDITION: withdrawn funds are returned to the client
+            balance.held = checked(balance.held.checked_add(amount))?;
+            balance.available = checked(balance.available.checked_sub(amount))?;
+        } else {
+            // POSTCONDITION: client held funds are removed from the client
+            balance.held = checked(balance.held.checked_sub(amount))?;
+        }
+        // POSTCONDITION: client account is frozen
+        client.locked = true;
+        // POSTCONDITION: transaction is charged back for good
+        tx.state = DisputeState::ChargedBack;
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!("Charged back {}, account locked", amount),
+        );
+        Ok((client, tx))
+    }
+}
+
+/// Records an administrative event in the transaction ledger; it moves no funds.
+fn account_transaction(event: &AccountEvent, kind: TransactionKind) -> Transaction {
+    Transaction {
+        id: event.tx,
+        client: event.client,
+        kind,
+        amount: Amount::ZERO,
+        state: DisputeState::Settled,
+        currency: Currency::UNSPECIFIED,
+        counterparty: None,
+        fee: None,
+        created_at: event.timestamp,
+    }
+}
+
+#[async_trait]
+impl Processor for LockProcessor {
+    type Event = AccountEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        event: &AccountEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must be unique
+        if maybe_tx.is_some() {
+            return Err(ProcessorError::TransactionExists);
+        }
+        // PRECONDITION: client must exist
+        let mut client = match maybe_client {
+            None => return Err(ProcessorError::ClientMissing),
+            Some(client) => client,
+        };
+        // PRECONDITION: client must not be locked
+        if client.locked {
+            return Err(ProcessorError::ClientAlreadyLocked);
+        }
+        // OK
         // POSTCONDITION: client account is frozen
         client.locked = true;
-        // POSTCONDITION: transaction is no longer under dispute
-        tx.disputed = false;
+        // POSTCONDITION: lock is recorded in the ledger
+        let tx = account_transaction(event, TransactionKind::Lock);
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!("Locked account"),
+        );
+        Ok((client, tx))
+    }
+}
+
+#[async_trait]
+impl Processor for UnlockProcessor {
+    type Event = AccountEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        event: &AccountEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must be unique
+        if maybe_tx.is_some() {
+            return Err(ProcessorError::TransactionExists);
+        }
+        // PRECONDITION: client must exist
+        let mut client = match maybe_client {
+            None => return Err(ProcessorError::ClientMissing),
+            Some(client) => client,
+        };
+        // PRECONDITION: client must be locked
+        if !client.locked {
+            return Err(ProcessorError::ClientNotLocked);
+        }
+        // OK
+        // POSTCONDITION: client account is reopened
+        client.locked = false;
+        // POSTCONDITION: unlock is recorded in the ledger
+        let tx = account_transaction(event, TransactionKind::Unlock);
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!("Unlocked account"),
+        );
+        Ok((client, tx))
+    }
+}
+
+#[async_trait]
+impl Processor for AdjustmentProcessor {
+    type Event = AdjustmentEvent;
+
+    /// Adjustments are corrections by support staff, so they also apply to locked accounts.
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        event: &AdjustmentEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must be unique
+        if maybe_tx.is_some() {
+            return Err(ProcessorError::TransactionExists);
+        }
+        // PRECONDITION: client must exist
+        let mut client = match maybe_client {
+            None => return Err(ProcessorError::ClientMissing),
+            Some(client) => client,
+        };
+        let available = checked(
+            client
+                .balance(event.currency)
+                .available
+                .checked_add(event.amount),
+        )?;
+        // PRECONDITION: a debit must not exceed client available balance in its currency
+        if event.amount.is_negative() && available.is_negative() {
+            return Err(ProcessorError::AdjustmentAboveBalance);
+        }
+        // OK
+        // POSTCONDITION: client available balance is corrected
+        client.balance_mut(event.currency).available = available;
+        // POSTCONDITION: new transaction created
+        let tx = Transaction {
+            id: event.tx,
+            client: event.client,
+            kind: TransactionKind::Adjustment,
+            amount: event.amount,
+            state: DisputeState::Settled,
+            currency: event.currency,
+            counterparty: None,
+            fee: None,
+            created_at: event.timestamp,
+        };
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Adjusted by {} {} ({}), available now {}",
+                event.amount, event.currency, event.reason, available
+            ),
+        );
+        Ok((client, tx))
+    }
+}
+
+#[async_trait]
+impl Processor for ConvertProcessor {
+    type Event = ConvertEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        event: &ConvertEvent,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must be unique
+        if maybe_tx.is_some() {
+            return Err(ProcessorError::TransactionExists);
+        }
+        // PRECONDITION: client must exist
+        let mut client = match maybe_client {
+            None => return Err(ProcessorError::ClientMissing),
+            Some(client) => client,
+        };
+        // PRECONDITION: client must not be locked
+        if client.locked {
+            return Err(ProcessorError::ClientLocked);
+        }
+        // PRECONDITION: the policy must have a rate for the currency pair
+        let rate = match policy.rates.get(event.currency, event.target) {
+            None => return Err(ProcessorError::RateMissing),
+            Some(rate) => rate,
+        };
+        // PRECONDITION: conversion must not exceed client balance in the source currency
+        if event.amount > client.balance(event.currency).available {
+            return Err(ProcessorError::ConversionAboveBalance);
+        }
+        let converted = checked(rate.convert(event.amount, policy.rounding))?;
+        // PRECONDITION: client must receive something in the target currency
+        if converted == Amount::ZERO {
+            return Err(ProcessorError::ConversionRoundsToZero);
+        }
+        // OK
+        // POSTCONDITION: source balance is debited, then target balance credited
+        let source = client.balance_mut(event.currency);
+        source.available = checked(source.available.checked_sub(event.amount))?;
+        let target = client.balance_mut(event.target);
+        target.available = checked(target.available.checked_add(converted))?;
+        // POSTCONDITION: new transaction created for the amount taken from the source
+        let tx = Transaction {
+            id: event.tx,
+            client: event.client,
+            kind: TransactionKind::Conversion,
+            amount: checked(event.amount.checked_neg())?,
+            state: DisputeState::Settled,
+            currency: event.currency,
+            counterparty: None,
+            fee: None,
+            created_at: event.timestamp,
+        };
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Converted {} {} into {} {} at {}",
+                event.amount, event.currency, converted, event.target, rate
+            ),
+        );
         Ok((client, tx))
     }
 }
 
+#[async_trait]
+impl CounterpartyProcessor for TransferProcessor {
+    type Event = TransferEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        maybe_counterparty: Option<Client>,
+        event: &TransferEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must be unique
+        if maybe_tx.is_some() {
+            return Err(ProcessorError::TransactionExists);
+        }
+        // PRECONDITION: source and destination must be different clients
+        if event.client == event.destination {
+            return Err(ProcessorError::TransferToSelf);
+        }
+        // PRECONDITION: source client must exist
+        let mut client = match maybe_client {
+            None => return Err(ProcessorError::ClientMissing),
+            Some(client) => client,
+        };
+        // PRECONDITION: source client must not be locked
+        if client.locked {
+            return Err(ProcessorError::ClientLocked);
+        }
+        // PRECONDITION: destination client must exist
+        let mut destination = match maybe_counterparty {
+            None => return Err(ProcessorError::DestinationMissing),
+            Some(destination) => destination,
+        };
+        // PRECONDITION: destination client must not be locked
+        if destination.locked {
+            return Err(ProcessorError::DestinationLocked);
+        }
+        // PRECONDITION: transfer must not exceed source balance in its currency
+        if event.amount > client.balance(event.currency).available {
+            return Err(ProcessorError::TransferAboveBalance);
+        }
+        // OK
+        // POSTCONDITION: source balance is debited, then destination balance credited
+        let source = client.balance_mut(event.currency);
+        source.available = checked(source.available.checked_sub(event.amount))?;
+        let target = destination.balance_mut(event.currency);
+        target.available = checked(target.available.checked_add(event.amount))?;
+        // POSTCONDITION: new transaction created for the source, naming the destination
+        let tx = Transaction {
+            id: event.tx,
+            client: event.client,
+            kind: TransactionKind::Transfer,
+            amount: checked(event.amount.checked_neg())?,
+            state: DisputeState::Settled,
+            currency: event.currency,
+            counterparty: Some(event.destination),
+            fee: None,
+            created_at: event.timestamp,
+        };
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Transferred {} {} to client {}",
+                event.amount, event.currency, event.destination
+            ),
+        );
+        Ok((client, destination, tx))
+    }
+}
+
+/// The transaction of a transfer dispute event, and the amount it moved.
+fn transfer_transaction(
+    maybe_tx: Option<Transaction>,
+) -> Result<(Transaction, Amount), ProcessorError> {
+    match maybe_tx {
+        None => Err(ProcessorError::TransactionMissing),
+        Some(tx) => {
+            // Transfers are recorded as debits of the source
+            let amount = checked(tx.amount.checked_neg())?;
+            Ok((tx, amount))
+        }
+    }
+}
+
+/// The source and destination of a disputed transfer, once they are known to match it.
+fn transfer_clients(
+    tx: &Transaction,
+    maybe_client: Option<Client>,
+    maybe_counterparty: Option<Client>,
+) -> Result<(Client, Client), ProcessorError> {
+    // PRECONDITION: client must exist
+    let client = match maybe_client {
+        None => return Err(ProcessorError::ClientMissing),
+        Some(client) => client,
+    };
+    // PRECONDITION: client must match tx, as the source of the transfer
+    if tx.client != client.id {
+        return Err(ProcessorError::ClientTransactionMismatch);
+    }
+    // PRECONDITION: destination client must exist
+    let destination = match maybe_counterparty {
+        None => return Err(ProcessorError::DestinationMissing),
+        Some(destination) => destination,
+    };
+    Ok((client, destination))
+}
+
+#[async_trait]
+impl CounterpartyProcessor for TransferDisputeProcessor {
+    type Event = ReferenceEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        maybe_counterparty: Option<Client>,
+        event: &ReferenceEvent,
+        policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must exist
+        let (mut tx, amount) = transfer_transaction(maybe_tx)?;
+        // PRECONDITION: transaction must be open to dispute
+        match tx.state {
+            DisputeState::Settled => (),
+            DisputeState::Resolved if policy.allow_redisputes => (),
+            DisputeState::Resolved => return Err(ProcessorError::RedisputeNotAllowed),
+            DisputeState::Disputed => return Err(ProcessorError::TransactionDisputed),
+            DisputeState::ChargedBack => return Err(ProcessorError::TransactionChargedBack),
+        }
+        // PRECONDITION: dispute must be within the policy's window, where both times are known
+        if !policy.within_dispute_window(tx.created_at, event.timestamp) {
+            return Err(ProcessorError::DisputeWindowExpired);
+        }
+        let (client, mut destination) = transfer_clients(&tx, maybe_client, maybe_counterparty)?;
+        // OK
+        // POSTCONDITION: destination funds are held, to maximum extent
+        let balance = destination.balance_mut(tx.currency);
+        balance.available = checked(balance.available.checked_sub(amount))?;
+        balance.held = checked(balance.held.checked_add(amount))?;
+        // POSTCONDITION: transaction is marked as currently disputed
+        tx.state = DisputeState::Disputed;
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Disputed transfer, holding {} of client {}",
+                amount, destination.id
+            ),
+        );
+        Ok((client, destination, tx))
+    }
+}
+
+#[async_trait]
+impl CounterpartyProcessor for TransferResolveProcessor {
+    type Event = ReferenceEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        maybe_counterparty: Option<Client>,
+        event: &ReferenceEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must exist
+        let (mut tx, amount) = transfer_transaction(maybe_tx)?;
+        // PRECONDITION: transaction must be under dispute
+        if tx.state != DisputeState::Disputed {
+            return Err(ProcessorError::TransactionNotDisputed);
+        }
+        let (client, mut destination) = transfer_clients(&tx, maybe_client, maybe_counterparty)?;
+        // OK
+        // POSTCONDITION: destination held funds are released and the transfer stands
+        let balance = destination.balance_mut(tx.currency);
+        balance.available = checked(balance.available.checked_add(amount))?;
+        balance.held = checked(balance.held.checked_sub(amount))?;
+        // POSTCONDITION: transaction's dispute is resolved
+        tx.state = DisputeState::Resolved;
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Resolved transfer dispute, releasing {} to client {}",
+                amount, destination.id
+            ),
+        );
+        Ok((client, destination, tx))
+    }
+}
+
+#[async_trait]
+impl CounterpartyProcessor for TransferChargebackProcessor {
+    type Event = ReferenceEvent;
+
+    async fn process_event(
+        maybe_tx: Option<Transaction>,
+        maybe_client: Option<Client>,
+        maybe_counterparty: Option<Client>,
+        event: &ReferenceEvent,
+        _policy: &Policy,
+        logger: &(dyn Logger + Sync),
+    ) -> Result<(Client, Client, Transaction), ProcessorError> {
+        // PRECONDITION: transaction must exist
+        let (mut tx, amount) = transfer_transaction(maybe_tx)?;
+        // PRECONDITION: transaction must be under dispute
+        if tx.state != DisputeState::Disputed {
+            return Err(ProcessorError::TransactionNotDisputed);
+        }
+        let (mut client, mut destination) =
+            transfer_clients(&tx, maybe_client, maybe_counterparty)?;
+        // OK
+        // POSTCONDITION: destination held funds are removed from the destination
+        let held = destination.balance_mut(tx.currency);
+        held.held = checked(held.held.checked_sub(amount))?;
+        // POSTCONDITION: funds are returned to the source
+        let source = client.balance_mut(tx.currency);
+        source.available = checked(source.available.checked_add(amount))?;
+        // POSTCONDITION: source account is frozen, as for any chargeback: the source reversed its
+        // own payment, as a depositor does, while the destination only gives back what it was
+        // sent, so it stays open
+        client.locked = true;
+        // POSTCONDITION: transaction is charged back for good
+        tx.state = DisputeState::ChargedBack;
+        logger.debug(
+            fields(event.client, event.tx),
+            format_args!(
+                "Charged back transfer of {} from client {}, account locked",
+                amount, destination.id
+            ),
+        );
+        Ok((client, destination, tx))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::logger::NoopLogger;
+    use crate::store::Balance;
+    use std::time::Duration;
 
     mod deposit_test {
-        use crate::runner::EventType;
-
         use super::*;
 
-        fn default_event(event_type: EventType) -> Event {
-            Event {
-                event_type,
+        fn default_event() -> FundsEvent {
+            FundsEvent {
                 client: 0,
                 tx: 0,
-                amount: Some(0f64),
+                amount: Amount::ZERO,
+                currency: Currency::UNSPECIFIED,
+                timestamp: None,
             }
         }
 
@@ -257,7 +998,9 @@ mod test {
             let result = DepositProcessor::process_event(
                 Some(Transaction::default()),
                 None,
-                &default_event(EventType::Deposit),
+                &default_event(),
+                &Policy::default(),
+                &NoopLogger,
             )
             .await;
             assert!(matches!(result, Err(ProcessorError::TransactionExists)));
@@ -265,44 +1008,66 @@ mod test {
 
         #[tokio::test]
         async fn deposit_fails_for_locked_client() {
-            let mut client = Client::default();
-            client.locked = true;
+            let client = Client {
+                locked: true,
+                ..Client::default()
+            };
             let result = DepositProcessor::process_event(
                 None,
                 Some(client),
-                &default_event(EventType::Deposit),
+                &default_event(),
+                &Policy::default(),
+                &NoopLogger,
             )
             .await;
             assert!(matches!(result, Err(ProcessorError::ClientLocked)));
         }
 
         #[tokio::test]
-        async fn deposit_fails_without_amount() {
-            let mut event = default_event(EventType::Deposit);
-            event.amount = None;
-            let result = DepositProcessor::process_event(None, None, &event).await;
-            assert!(matches!(result, Err(ProcessorError::NoAmount)));
+        async fn deposit_fails_on_balance_overflow() {
+            let mut event = default_event();
+            event.amount = Amount::from_units(1);
+            let client = client(i64::MAX, 0);
+            let result = DepositProcessor::process_event(
+                None,
+                Some(client),
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert!(matches!(result, Err(ProcessorError::AmountOverflow)));
         }
 
         #[tokio::test]
         async fn deposit_succeeds_with_no_client() {
-            let mut event = default_event(EventType::Deposit);
-            let amount = 1f64;
-            event.amount = Some(amount);
-            let result = DepositProcessor::process_event(None, None, &event).await;
+            let mut event = default_event();
+            let amount = Amount::from_units(10_000);
+            event.amount = amount;
+            let result = DepositProcessor::process_event(
+                None,
+                None,
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
             assert!(result.is_ok());
-            let (client, tx) = result.unwrap();
             let expected_client = Client {
                 id: 0,
-                available: amount,
-                held: 0f64,
-                locked: false,
+                ..client(10_000, 0)
             };
+            let (client, tx) = result.unwrap();
             let expected_tx = Transaction {
                 id: 0,
                 amount,
                 client: 0,
-                disputed: false,
+                kind: TransactionKind::Deposit,
+                state: DisputeState::Settled,
+                currency: Currency::UNSPECIFIED,
+                counterparty: None,
+                fee: None,
+                created_at: None,
             };
             assert_eq!(client, expected_client);
             assert_eq!(tx, expected_tx);
@@ -310,32 +1075,33 @@ mod test {
 
         #[tokio::test]
         async fn deposit_succeeds_with_existing_client() {
-            let mut event = default_event(EventType::Deposit);
-            let amount = 1f64;
-            let initial_client = Client {
-                id: 1,
-                available: 2f64,
-                held: 0f64,
-                locked: false,
-            };
+            let mut event = default_event();
+            let amount = Amount::from_units(10_000);
+            let initial_client = client(20_000, 0);
 
             event.client = 1;
-            event.amount = Some(amount);
-            let result =
-                DepositProcessor::process_event(None, Some(initial_client.clone()), &event).await;
+            event.amount = amount;
+            let result = DepositProcessor::process_event(
+                None,
+                Some(initial_client.clone()),
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
             assert!(result.is_ok());
+            let expected_client = client(30_000, 0);
             let (client, tx) = result.unwrap();
-            let expected_client = Client {
-                id: 1,
-                available: initial_client.available + amount,
-                held: 0f64,
-                locked: false,
-            };
             let expected_tx = Transaction {
                 id: 0,
                 amount,
                 client: 1,
-                disputed: false,
+                kind: TransactionKind::Deposit,
+                state: DisputeState::Settled,
+                currency: Currency::UNSPECIFIED,
+                counterparty: None,
+                fee: None,
+                created_at: None,
             };
             assert_eq!(client, expected_client);
             assert_eq!(tx, expected_tx);
@@ -343,15 +1109,1031 @@ mod test {
     }
 
     mod withdrawal_test {
-        // TODO
-    }
-    mod dispute_test {
-        // TODO
+        use super::*;
+
+        async fn withdraw(
+            amount: i64,
+            maybe_client: Option<Client>,
+        ) -> Result<(Client, Transaction), ProcessorError> {
+            let event = FundsEvent {
+                client: 1,
+                tx: 2,
+                amount: Amount::from_units(amount),
+                currency: Currency::UNSPECIFIED,
+                timestamp: None,
+            };
+            WithdrawalProcessor::process_event(
+                None,
+                maybe_client,
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await
+        }
+
+        #[tokio::test]
+        async fn withdrawal_succeeds_within_available_balance() {
+            let result = withdraw(10, Some(client(15, 5))).await;
+            assert_eq!(
+                result,
+                Ok((client(5, 5), transaction(-10, DisputeState::Settled)))
+            );
+        }
+
+        #[tokio::test]
+        async fn withdrawal_fails_above_available_balance() {
+            let result = withdraw(16, Some(client(15, 5))).await;
+            assert_eq!(result, Err(ProcessorError::WithdrawalAboveBalance));
+        }
+
+        #[tokio::test]
+        async fn withdrawal_fails_for_missing_or_locked_client() {
+            let result = withdraw(10, None).await;
+            assert_eq!(result, Err(ProcessorError::ClientMissing));
+            let result = withdraw(10, Some(locked(client(15, 0)))).await;
+            assert_eq!(result, Err(ProcessorError::ClientLocked));
+        }
     }
+
+    fn client(available: i64, held: i64) -> Client {
+        let mut client = Client {
+            id: 1,
+            ..Client::default()
+        };
+        *client.balance_mut(Currency::UNSPECIFIED) = Balance {
+            available: Amount::from_units(available),
+            held: Amount::from_units(held),
+        };
+        client
+    }
+
+    fn transaction(amount: i64, state: DisputeState) -> Transaction {
+        Transaction {
+            id: 2,
+            client: 1,
+            kind: if amount < 0 {
+                TransactionKind::Withdrawal
+            } else {
+                TransactionKind::Deposit
+            },
+            amount: Amount::from_units(amount),
+            state,
+            currency: Currency::UNSPECIFIED,
+            counterparty: None,
+            fee: None,
+            created_at: None,
+        }
+    }
+
+    fn reference_event() -> ReferenceEvent {
+        ReferenceEvent {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        }
+    }
+
+    fn disputable_withdrawals() -> Policy {
+        Policy::default().with_disputable_withdrawals(true)
+    }
+
+    mod dispute_test {
+        use super::*;
+
+        async fn dispute(
+            tx: Transaction,
+            client: Client,
+            policy: &Policy,
+        ) -> Result<(Client, Transaction), ProcessorError> {
+            DisputeProcessor::process_event(
+                Some(tx),
+                Some(client),
+                &reference_event(),
+                policy,
+                &NoopLogger,
+            )
+            .await
+        }
+
+        #[tokio::test]
+        async fn dispute_fails_if_transaction_missing() {
+            let result = DisputeProcessor::process_event(
+                None,
+                Some(client(10, 0)),
+                &reference_event(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert!(matches!(result, Err(ProcessorError::TransactionMissing)));
+        }
+
+        #[tokio::test]
+        async fn dispute_fails_if_transaction_disputed() {
+            for policy in [Policy::default(), disputable_withdrawals()] {
+                let result = dispute(
+                    transaction(10, DisputeState::Disputed),
+                    client(0, 10),
+                    &policy,
+                )
+                .await;
+                assert!(matches!(result, Err(ProcessorError::TransactionDisputed)));
+            }
+        }
+
+        #[tokio::test]
+        async fn dispute_fails_for_mismatched_client() {
+            let other = Client {
+                id: 3,
+                ..client(10, 0)
+            };
+            let result = dispute(
+                transaction(10, DisputeState::Settled),
+                other,
+                &Policy::default(),
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(ProcessorError::ClientTransactionMismatch)
+            ));
+        }
+
+        #[tokio::test]
+        async fn dispute_holds_deposit_under_any_policy() {
+            for policy in [Policy::default(), disputable_withdrawals()] {
+                let result = dispute(
+                    transaction(10, DisputeState::Settled),
+                    client(15, 0),
+                    &policy,
+                )
+                .await;
+                assert_eq!(
+                    result,
+                    Ok((client(5, 10), transaction(10, DisputeState::Disputed)))
+                );
+            }
+        }
+
+        #[tokio::test]
+        async fn dispute_holds_funds_in_transaction_currency() {
+            let eur: Currency = "EUR".parse().unwrap();
+            let mut initial = client(15, 0);
+            initial.balance_mut(eur).available = Amount::from_units(10);
+            let tx = Transaction {
+                currency: eur,
+                ..transaction(10, DisputeState::Settled)
+            };
+            let (client, tx) = dispute(tx, initial, &Policy::default()).await.unwrap();
+            assert_eq!(tx.state, DisputeState::Disputed);
+            assert_eq!(
+                client.balance(eur),
+                Balance {
+                    available: Amount::ZERO,
+                    held: Amount::from_units(10),
+                }
+            );
+            assert_eq!(
+                client.balance(Currency::UNSPECIFIED).available,
+                Amount::from_units(15)
+            );
+        }
+
+        #[tokio::test]
+        async fn dispute_fails_for_withdrawal_by_default() {
+            let result = dispute(
+                transaction(-10, DisputeState::Settled),
+                client(5, 0),
+                &Policy::default(),
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(ProcessorError::WithdrawalNotDisputable)
+            ));
+        }
+
+        #[tokio::test]
+        async fn dispute_holds_withdrawal_on_credit_side_when_allowed() {
+            let result = dispute(
+                transaction(-10, DisputeState::Settled),
+                client(5, 0),
+                &disputable_withdrawals(),
+            )
+            .await;
+            assert_eq!(
+                result,
+                Ok((client(5, 10), transaction(-10, DisputeState::Disputed)))
+            );
+        }
+
+        #[tokio::test]
+        async fn dispute_fails_for_administrative_transactions() {
+            for kind in [TransactionKind::Adjustment, TransactionKind::Lock] {
+                let tx = Transaction {
+                    kind,
+                    ..transaction(10, DisputeState::Settled)
+                };
+                let result = dispute(tx, client(10, 0), &disputable_withdrawals()).await;
+                assert_eq!(result, Err(ProcessorError::TransactionNotDisputable));
+            }
+        }
+
+        #[tokio::test]
+        async fn redispute_depends_on_policy() {
+            let resolved = transaction(10, DisputeState::Resolved);
+            let result = dispute(resolved.clone(), client(10, 0), &Policy::default()).await;
+            assert_eq!(result, Err(ProcessorError::RedisputeNotAllowed));
+            let policy = Policy::default().with_redisputes(true);
+            let result = dispute(resolved, client(10, 0), &policy).await;
+            assert_eq!(
+                result,
+                Ok((client(0, 10), transaction(10, DisputeState::Disputed)))
+            );
+        }
+
+        #[tokio::test]
+        async fn dispute_fails_after_chargeback_under_any_policy() {
+            let policy = Policy::default().with_redisputes(true);
+            for policy in [Policy::default(), policy] {
+                let tx = transaction(10, DisputeState::ChargedBack);
+                let result = dispute(tx, client(0, 0), &policy).await;
+                assert_eq!(result, Err(ProcessorError::TransactionChargedBack));
+            }
+        }
+
+        #[tokio::test]
+        async fn dispute_is_limited_by_window() {
+            let policy = Policy::default().with_dispute_window(Some(Duration::from_secs(60)));
+            let tx = Transaction {
+                created_at: Some(1_000),
+                ..transaction(10, DisputeState::Settled)
+            };
+            for (disputed_at, expected) in [(Some(1_060), true), (Some(1_061), false), (None, true)]
+            {
+                let event = ReferenceEvent {
+                    timestamp: disputed_at,
+                    ..reference_event()
+                };
+                let result = DisputeProcessor::process_event(
+                    Some(tx.clone()),
+                    Some(client(10, 0)),
+                    &event,
+                    &policy,
+                    &NoopLogger,
+                )
+                .await;
+                if expected {
+                    assert!(result.is_ok(), "{:?}", disputed_at);
+                } else {
+                    assert_eq!(result, Err(ProcessorError::DisputeWindowExpired));
+                }
+            }
+        }
+    }
+
     mod resolve_test {
-        // TODO
+        use super::*;
+
+        async fn resolve(
+            tx: Transaction,
+            client: Client,
+        ) -> Result<(Client, Transaction), ProcessorError> {
+            ResolveProcessor::process_event(
+                Some(tx),
+                Some(client),
+                &reference_event(),
+                &disputable_withdrawals(),
+                &NoopLogger,
+            )
+            .await
+        }
+
+        #[tokio::test]
+        async fn resolve_fails_if_not_disputed() {
+            let result = resolve(transaction(10, DisputeState::Settled), client(10, 0)).await;
+            assert!(matches!(
+                result,
+                Err(ProcessorError::TransactionNotDisputed)
+            ));
+        }
+
+        #[tokio::test]
+        async fn resolve_releases_deposit_to_available() {
+            let result = resolve(transaction(10, DisputeState::Disputed), client(5, 10)).await;
+            assert_eq!(
+                result,
+                Ok((client(15, 0), transaction(10, DisputeState::Resolved)))
+            );
+        }
+
+        #[tokio::test]
+        async fn resolve_releases_withdrawal_hold() {
+            let result = resolve(transaction(-10, DisputeState::Disputed), client(5, 10)).await;
+            assert_eq!(
+                result,
+                Ok((client(5, 0), transaction(-10, DisputeState::Resolved)))
+            );
+        }
     }
+
     mod chargeback_test {
-        // TODO
+        use super::*;
+
+        async fn chargeback(
+            tx: Transaction,
+            client: Client,
+        ) -> Result<(Client, Transaction), ProcessorError> {
+            ChargebackProcessor::process_event(
+                Some(tx),
+                Some(client),
+                &reference_event(),
+                &disputable_withdrawals(),
+                &NoopLogger,
+            )
+            .await
+        }
+
+        #[tokio::test]
+        async fn chargeback_fails_if_not_disputed() {
+            let result = chargeback(transaction(10, DisputeState::Settled), client(10, 0)).await;
+            assert!(matches!(
+                result,
+                Err(ProcessorError::TransactionNotDisputed)
+            ));
+        }
+
+        #[tokio::test]
+        async fn chargeback_removes_deposit_and_locks() {
+            let result = chargeback(transaction(10, DisputeState::Disputed), client(5, 10)).await;
+            assert_eq!(
+                result,
+                Ok((
+                    locked(client(5, 0)),
+                    transaction(10, DisputeState::ChargedBack)
+                ))
+            );
+        }
+
+        #[tokio::test]
+        async fn chargeback_returns_withdrawal_to_available_and_locks() {
+            let result = chargeback(transaction(-10, DisputeState::Disputed), client(5, 10)).await;
+            assert_eq!(
+                result,
+                Ok((
+                    locked(client(15, 0)),
+                    transaction(-10, DisputeState::ChargedBack)
+                ))
+            );
+        }
+    }
+
+    fn account_event() -> AccountEvent {
+        AccountEvent {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        }
+    }
+
+    fn locked(client: Client) -> Client {
+        Client {
+            locked: true,
+            ..client
+        }
+    }
+
+    mod lock_test {
+        use super::*;
+
+        #[tokio::test]
+        async fn lock_fails_for_missing_client() {
+            let result = LockProcessor::process_event(
+                None,
+                None,
+                &account_event(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::ClientMissing));
+        }
+
+        #[tokio::test]
+        async fn lock_fails_if_already_locked() {
+            let result = LockProcessor::process_event(
+                None,
+                Some(locked(client(10, 0))),
+                &account_event(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::ClientAlreadyLocked));
+        }
+
+        #[tokio::test]
+        async fn lock_and_unlock_toggle_account_and_record_transaction() {
+            let (client, tx) = LockProcessor::process_event(
+                None,
+                Some(client(10, 0)),
+                &account_event(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await
+            .unwrap();
+            assert!(client.locked);
+            assert_eq!(tx.kind, TransactionKind::Lock);
+            assert_eq!(tx.amount, Amount::ZERO);
+
+            let event = AccountEvent {
+                tx: 3,
+                ..account_event()
+            };
+            let (client, tx) = UnlockProcessor::process_event(
+                None,
+                Some(client),
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await
+            .unwrap();
+            assert!(!client.locked);
+            assert_eq!(tx.kind, TransactionKind::Unlock);
+        }
+
+        #[tokio::test]
+        async fn unlock_fails_if_not_locked() {
+            let result = UnlockProcessor::process_event(
+                None,
+                Some(client(10, 0)),
+                &account_event(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::ClientNotLocked));
+        }
+    }
+
+    mod adjustment_test {
+        use super::*;
+
+        async fn adjust(
+            amount: i64,
+            client: Client,
+        ) -> Result<(Client, Transaction), ProcessorError> {
+            let event = AdjustmentEvent {
+                client: 1,
+                tx: 2,
+                amount: Amount::from_units(amount),
+                currency: Currency::UNSPECIFIED,
+                reason: "correction".to_owned(),
+                timestamp: None,
+            };
+            AdjustmentProcessor::process_event(
+                None,
+                Some(client),
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await
+        }
+
+        #[tokio::test]
+        async fn adjustment_credits_and_debits_locked_account() {
+            let (client, tx) = adjust(5, locked(client(10, 3))).await.unwrap();
+            assert_eq!(client, locked(super::client(15, 3)));
+            assert_eq!(tx.kind, TransactionKind::Adjustment);
+            assert_eq!(tx.amount, Amount::from_units(5));
+            let (client, _) = adjust(-10, client).await.unwrap();
+            assert_eq!(client, locked(super::client(5, 3)));
+        }
+
+        #[tokio::test]
+        async fn adjustment_fails_if_debit_exceeds_available() {
+            let result = adjust(-11, client(10, 5)).await;
+            assert_eq!(result, Err(ProcessorError::AdjustmentAboveBalance));
+        }
+    }
+
+    mod convert_test {
+        use super::*;
+        use crate::rates::{RateTable, RoundingMode};
+
+        fn eur() -> Currency {
+            "EUR".parse().unwrap()
+        }
+
+        /// Converts `amount` of the unspecified currency into euros at 0.5.
+        async fn convert(
+            amount: i64,
+            client: Client,
+            policy: &Policy,
+        ) -> Result<(Client, Transaction), ProcessorError> {
+            let event = ConvertEvent {
+                client: 1,
+                tx: 2,
+                amount: Amount::from_units(amount),
+                currency: Currency::UNSPECIFIED,
+                target: eur(),
+                timestamp: None,
+            };
+            ConvertProcessor::process_event(None, Some(client), &event, policy, &NoopLogger).await
+        }
+
+        fn rates() -> Policy {
+            let rate = "0.5".parse().unwrap();
+            Policy::default().with_rates(RateTable::default().with_rate(
+                Currency::UNSPECIFIED,
+                eur(),
+                rate,
+            ))
+        }
+
+        #[tokio::test]
+        async fn convert_moves_rounded_funds_between_currencies() {
+            let (client, tx) = convert(3, client(10, 2), &rates()).await.unwrap();
+            assert_eq!(
+                client.balance(Currency::UNSPECIFIED).available,
+                Amount::from_units(7)
+            );
+            // 0.0003 * 0.5 = 0.00015, a tie rounded to the even 0.0002
+            assert_eq!(client.balance(eur()).available, Amount::from_units(2));
+            assert_eq!(tx.kind, TransactionKind::Conversion);
+            assert_eq!(tx.amount, Amount::from_units(-3));
+            assert_eq!(tx.currency, Currency::UNSPECIFIED);
+
+            let truncating = rates().with_rounding(RoundingMode::Down);
+            let (client, _) = convert(3, client, &truncating).await.unwrap();
+            assert_eq!(client.balance(eur()).available, Amount::from_units(3));
+        }
+
+        #[tokio::test]
+        async fn convert_fails_without_rate() {
+            let result = convert(3, client(10, 0), &Policy::default()).await;
+            assert_eq!(result, Err(ProcessorError::RateMissing));
+        }
+
+        #[tokio::test]
+        async fn convert_fails_if_amount_exceeds_source_balance() {
+            let result = convert(11, client(10, 5), &rates()).await;
+            assert_eq!(result, Err(ProcessorError::ConversionAboveBalance));
+            let result = convert(1, locked(client(10, 0)), &rates()).await;
+            assert_eq!(result, Err(ProcessorError::ClientLocked));
+        }
+
+        #[tokio::test]
+        async fn convert_fails_if_converted_amount_rounds_to_zero() {
+            // 0.0001 * 0.5 = 0.00005, a tie rounded to the even 0.0000
+            let result = convert(1, client(10, 0), &rates()).await;
+            assert_eq!(result, Err(ProcessorError::ConversionRoundsToZero));
+            let rounding_up = rates().with_rounding(RoundingMode::Up);
+            assert!(convert(1, client(10, 0), &rounding_up).await.is_ok());
+        }
+    }
+
+    mod transfer_test {
+        use super::*;
+
+        fn destination(available: i64, held: i64) -> Client {
+            Client {
+                id: 3,
+                ..client(available, held)
+            }
+        }
+
+        fn transfer_tx(amount: i64, state: DisputeState) -> Transaction {
+            Transaction {
+                kind: TransactionKind::Transfer,
+                counterparty: Some(3),
+                fee: None,
+                ..transaction(-amount, state)
+            }
+        }
+
+        async fn transfer(
+            amount: i64,
+            source: Client,
+            destination: Option<Client>,
+        ) -> Result<(Client, Client, Transaction), ProcessorError> {
+            let event = TransferEvent {
+                client: 1,
+                tx: 2,
+                destination: 3,
+                amount: Amount::from_units(amount),
+                currency: Currency::UNSPECIFIED,
+                timestamp: None,
+            };
+            TransferProcessor::process_event(
+                None,
+                Some(source),
+                destination,
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await
+        }
+
+        fn reference() -> ReferenceEvent {
+            ReferenceEvent {
+                client: 1,
+                tx: 2,
+                timestamp: None,
+            }
+        }
+
+        #[tokio::test]
+        async fn transfer_moves_funds_between_clients() {
+            let result = transfer(4, client(10, 1), Some(destination(2, 0))).await;
+            assert_eq!(
+                result,
+                Ok((
+                    client(6, 1),
+                    destination(6, 0),
+                    transfer_tx(4, DisputeState::Settled)
+                ))
+            );
+        }
+
+        #[tokio::test]
+        async fn transfer_fails_unless_both_clients_can_take_part() {
+            let result = transfer(4, client(10, 0), None).await;
+            assert_eq!(result, Err(ProcessorError::DestinationMissing));
+            let result = transfer(4, locked(client(10, 0)), Some(destination(0, 0))).await;
+            assert_eq!(result, Err(ProcessorError::ClientLocked));
+            let result = transfer(4, client(10, 0), Some(locked(destination(0, 0)))).await;
+            assert_eq!(result, Err(ProcessorError::DestinationLocked));
+            let result = transfer(11, client(10, 0), Some(destination(0, 0))).await;
+            assert_eq!(result, Err(ProcessorError::TransferAboveBalance));
+        }
+
+        #[tokio::test]
+        async fn transfer_fails_to_same_client() {
+            let event = TransferEvent {
+                client: 1,
+                tx: 2,
+                destination: 1,
+                amount: Amount::from_units(1),
+                currency: Currency::UNSPECIFIED,
+                timestamp: None,
+            };
+            let result = TransferProcessor::process_event(
+                None,
+                Some(client(10, 0)),
+                Some(client(10, 0)),
+                &event,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::TransferToSelf));
+        }
+
+        #[tokio::test]
+        async fn dispute_holds_transferred_funds_at_destination() {
+            let result = TransferDisputeProcessor::process_event(
+                Some(transfer_tx(4, DisputeState::Settled)),
+                Some(client(6, 0)),
+                Some(destination(5, 0)),
+                &reference(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(
+                result,
+                Ok((
+                    client(6, 0),
+                    destination(1, 4),
+                    transfer_tx(4, DisputeState::Disputed)
+                ))
+            );
+        }
+
+        #[tokio::test]
+        async fn resolve_releases_transferred_funds_to_destination() {
+            let result = TransferResolveProcessor::process_event(
+                Some(transfer_tx(4, DisputeState::Disputed)),
+                Some(client(6, 0)),
+                Some(destination(1, 4)),
+                &reference(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(
+                result,
+                Ok((
+                    client(6, 0),
+                    destination(5, 0),
+                    transfer_tx(4, DisputeState::Resolved)
+                ))
+            );
+        }
+
+        #[tokio::test]
+        async fn chargeback_returns_transferred_funds_to_source() {
+            let result = TransferChargebackProcessor::process_event(
+                Some(transfer_tx(4, DisputeState::Disputed)),
+                Some(client(6, 0)),
+                Some(destination(1, 4)),
+                &reference(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(
+                result,
+                Ok((
+                    locked(client(10, 0)),
+                    destination(1, 0),
+                    transfer_tx(4, DisputeState::ChargedBack)
+                ))
+            );
+        }
+
+        #[tokio::test]
+        async fn chargeback_locks_source_but_not_destination() {
+            let (source, destination, _) = TransferChargebackProcessor::process_event(
+                Some(transfer_tx(4, DisputeState::Disputed)),
+                Some(client(6, 0)),
+                Some(destination(1, 4)),
+                &reference(),
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await
+            .unwrap();
+            assert!(source.locked);
+            assert!(!destination.locked);
+        }
+    }
+
+    mod fee_test {
+        use super::*;
+        use crate::fees::{FeeSchedule, FeeTier};
+
+        /// Charges 0.0001 plus 10% on deposits and withdrawals, paid to client 9.
+        fn fees() -> Policy {
+            let tier = FeeTier {
+                flat: Amount::from_units(1),
+                rate: Some("0.1".parse().unwrap()),
+                ..FeeTier::default()
+            };
+            Policy::default().with_house_account(9).with_fees(
+                FeeSchedule::default()
+                    .with_tier(EventType::Deposit, tier)
+                    .with_tier(EventType::Withdrawal, tier),
+            )
+        }
+
+        fn event(amount: i64) -> FundsEvent {
+            FundsEvent {
+                client: 1,
+                tx: 2,
+                amount: Amount::from_units(amount),
+                currency: Currency::UNSPECIFIED,
+                timestamp: None,
+            }
+        }
+
+        fn fee(amount: i64) -> Option<Fee> {
+            Some(Fee {
+                account: 9,
+                amount: Amount::from_units(amount),
+            })
+        }
+
+        #[tokio::test]
+        async fn deposit_credits_amount_less_fee() {
+            let result = DepositProcessor::process_event(
+                None,
+                Some(client(5, 0)),
+                &event(20),
+                &fees(),
+                &NoopLogger,
+            )
+            .await;
+            let tx = Transaction {
+                fee: fee(3),
+                ..transaction(20, DisputeState::Settled)
+            };
+            assert_eq!(result, Ok((client(22, 0), tx)));
+        }
+
+        #[tokio::test]
+        async fn deposit_fails_if_fee_exceeds_amount() {
+            let policy = fees().with_fees(FeeSchedule::default().with_tier(
+                EventType::Deposit,
+                FeeTier {
+                    flat: Amount::from_units(5),
+                    ..FeeTier::default()
+                },
+            ));
+            let result =
+                DepositProcessor::process_event(None, None, &event(4), &policy, &NoopLogger).await;
+            assert_eq!(result, Err(ProcessorError::FeeAboveDeposit));
+        }
+
+        #[tokio::test]
+        async fn withdrawal_debits_amount_plus_fee() {
+            let result = WithdrawalProcessor::process_event(
+                None,
+                Some(client(25, 0)),
+                &event(20),
+                &fees(),
+                &NoopLogger,
+            )
+            .await;
+            let tx = Transaction {
+                fee: fee(3),
+                ..transaction(-20, DisputeState::Settled)
+            };
+            assert_eq!(result, Ok((client(2, 0), tx)));
+
+            let result = WithdrawalProcessor::process_event(
+                None,
+                Some(client(22, 0)),
+                &event(20),
+                &fees(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::WithdrawalAboveBalance));
+        }
+
+        #[tokio::test]
+        async fn dispute_after_fee_holds_amount_received() {
+            let reference = ReferenceEvent {
+                client: 1,
+                tx: 2,
+                timestamp: None,
+            };
+            let deposit = Transaction {
+                fee: fee(3),
+                ..transaction(20, DisputeState::Settled)
+            };
+            let (disputed, tx) = DisputeProcessor::process_event(
+                Some(deposit),
+                Some(client(22, 0)),
+                &reference,
+                &fees(),
+                &NoopLogger,
+            )
+            .await
+            .unwrap();
+            assert_eq!(disputed, client(5, 17));
+            let result = ChargebackProcessor::process_event(
+                Some(tx),
+                Some(disputed),
+                &reference,
+                &fees(),
+                &NoopLogger,
+            )
+            .await;
+            let tx = Transaction {
+                fee: fee(3),
+                ..transaction(20, DisputeState::ChargedBack)
+            };
+            assert_eq!(result, Ok((locked(client(5, 0)), tx)));
+        }
+    }
+
+    mod limit_test {
+        use super::*;
+        use crate::event::Timestamp;
+        use crate::limits::{LimitTable, Limits};
+
+        const DAY: Timestamp = 24 * 60 * 60;
+
+        /// Deposits up to 10, withdrawals up to 5 and 8 a day for client 1.
+        fn limits() -> Policy {
+            Policy::default().with_limits(LimitTable::default().with_limits(
+                Some(1),
+                None,
+                Limits {
+                    max_deposit: Some(Amount::from_units(10)),
+                    max_withdrawal: Some(Amount::from_units(5)),
+                    max_daily_withdrawal: Some(Amount::from_units(8)),
+                },
+            ))
+        }
+
+        fn event(amount: i64, timestamp: Timestamp) -> FundsEvent {
+            FundsEvent {
+                client: 1,
+                tx: 2,
+                amount: Amount::from_units(amount),
+                currency: Currency::UNSPECIFIED,
+                timestamp: Some(timestamp),
+            }
+        }
+
+        fn withdrawn(client: &mut Client, day: u64, amount: i64) {
+            let total = DailyTotal {
+                day,
+                amount: Amount::from_units(amount),
+            };
+            client.withdrawn_today.insert(Currency::UNSPECIFIED, total);
+        }
+
+        #[tokio::test]
+        async fn deposit_fails_above_limit() {
+            let policy = limits();
+            let result =
+                DepositProcessor::process_event(None, None, &event(11, 0), &policy, &NoopLogger)
+                    .await;
+            assert_eq!(result, Err(ProcessorError::DepositLimitExceeded));
+            let result =
+                DepositProcessor::process_event(None, None, &event(10, 0), &policy, &NoopLogger)
+                    .await;
+            assert!(result.is_ok());
+        }
+
+        #[tokio::test]
+        async fn withdrawal_fails_above_limit() {
+            let result = WithdrawalProcessor::process_event(
+                None,
+                Some(client(20, 0)),
+                &event(6, 0),
+                &limits(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::WithdrawalLimitExceeded));
+        }
+
+        #[tokio::test]
+        async fn withdrawal_counts_towards_daily_total() {
+            let mut before = client(20, 0);
+            withdrawn(&mut before, 1, 4);
+            let (after, _) = WithdrawalProcessor::process_event(
+                None,
+                Some(before.clone()),
+                &event(4, DAY + 1),
+                &limits(),
+                &NoopLogger,
+            )
+            .await
+            .unwrap();
+            let mut expected = client(16, 0);
+            withdrawn(&mut expected, 1, 8);
+            assert_eq!(after, expected);
+
+            let result = WithdrawalProcessor::process_event(
+                None,
+                Some(after),
+                &event(1, DAY + 2),
+                &limits(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::DailyWithdrawalLimitExceeded));
+
+            let (after, _) = WithdrawalProcessor::process_event(
+                None,
+                Some(before),
+                &event(5, 2 * DAY),
+                &limits(),
+                &NoopLogger,
+            )
+            .await
+            .unwrap();
+            let mut expected = client(15, 0);
+            withdrawn(&mut expected, 2, 5);
+            assert_eq!(after, expected);
+        }
+
+        #[tokio::test]
+        async fn withdrawal_fails_without_timestamp_under_daily_limit() {
+            let untimed = FundsEvent {
+                timestamp: None,
+                ..event(1, 0)
+            };
+            let result = WithdrawalProcessor::process_event(
+                None,
+                Some(client(20, 0)),
+                &untimed,
+                &limits(),
+                &NoopLogger,
+            )
+            .await;
+            assert_eq!(result, Err(ProcessorError::DailyLimitWithoutTimestamp));
+            let result = WithdrawalProcessor::process_event(
+                None,
+                Some(client(20, 0)),
+                &untimed,
+                &Policy::default(),
+                &NoopLogger,
+            )
+            .await;
+            assert!(result.is_ok());
+        }
     }
 }
@@ -0,0 +1,289 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::amount::Amount;
+use crate::currency::Currency;
+
+/// Most decimal places a rate may have.
+const RATE_DECIMALS: u32 = 12;
+
+#[derive(Error, Debug)]
+pub enum RatesError {
+    #[error("Rate table could not be accessed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Rate table is malformed: {0}")]
+    Malformed(#[from] csv::Error),
+    #[error("Rate table has more than one rate from {from} to {to}")]
+    DuplicateRate { from: Currency, to: Currency },
+    #[error("Rate table has a rate from {0} to itself")]
+    SameCurrency(Currency),
+}
+
+#[derive(Error, PartialEq, Debug)]
+#[error("Rate {0:?} is not a positive decimal with at most twelve decimal places")]
+pub struct RateError(String);
+
+/// How a converted amount with more than four decimal places is rounded.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RoundingMode {
+    /// To the nearest amount, ties to the even one ("banker's rounding").
+    #[default]
+    HalfEven,
+    /// To the nearest amount, ties away from zero.
+    HalfUp,
+    /// Towards zero, i.e. truncated.
+    Down,
+    /// Away from zero.
+    Up,
+}
+
+impl RoundingMode {
+    /// Divides `dividend` by the positive `divisor`, rounding the quotient in this mode.
+    fn divide(self, dividend: i128, divisor: i128) -> i128 {
+        // Both truncate towards zero, so the remainder has the sign of the dividend
+        let quotient = dividend / divisor;
+        let remainder = dividend % divisor;
+        if remainder == 0 {
+            return quotient;
+        }
+        let away = quotient + dividend.signum();
+        let half = (remainder.abs() * 2).cmp(&divisor);
+        match (self, half) {
+            (RoundingMode::Down, _) => quotient,
+            (RoundingMode::Up, _) => away,
+            (_, std::cmp::Ordering::Less) => quotient,
+            (_, std::cmp::Ordering::Greater) => away,
+            (RoundingMode::HalfUp, std::cmp::Ordering::Equal) => away,
+            (RoundingMode::HalfEven, std::cmp::Ordering::Equal) if quotient % 2 == 0 => quotient,
+            (RoundingMode::HalfEven, std::cmp::Ordering::Equal) => away,
+        }
+    }
+}
+
+/// Exchange rate: the amount of the target currency bought by one unit of the source currency.
+///
+/// Kept as an exact decimal, so conversions only round once, to the precision of an `Amount`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Rate {
+    /// The rate scaled by `10^decimals`.
+    mantissa: u64,
+    decimals: u32,
+}
+
+impl Rate {
+    /// `amount` in the target currency, or `None` if it overflows an `Amount`.
+    pub fn convert(self, amount: Amount, rounding: RoundingMode) -> Option<Amount> {
+        // An i64 times a u64 always fits in an i128
+        let product = i128::from(amount.units()) * i128::from(self.mantissa);
+        let units = rounding.divide(product, 10i128.pow(self.decimals));
+        i64::try_from(units).ok().map(Amount::from_units)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = RateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RateError(s.to_owned());
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let digits = whole.bytes().chain(fraction.bytes());
+        if whole.len() + fraction.len() == 0 || !digits.clone().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if fraction.len() > RATE_DECIMALS as usize {
+            return Err(invalid());
+        }
+        let mut mantissa: u64 = 0;
+        for b in digits {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(u64::from(b - b'0')))
+                .ok_or_else(invalid)?;
+        }
+        if mantissa == 0 {
+            return Err(invalid());
+        }
+        Ok(Rate {
+            mantissa,
+            decimals: fraction.len() as u32,
+        })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10u64.pow(self.decimals);
+        write!(f, "{}", self.mantissa / scale)?;
+        if self.decimals > 0 {
+            write!(
+                f,
+                ".{:0width$}",
+                self.mantissa % scale,
+                width = self.decimals as usize
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct RateVisitor;
+
+impl<'de> Visitor<'de> for RateVisitor {
+    type Value = Rate;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a positive decimal rate with at most twelve decimal places")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(RateVisitor)
+    }
+}
+
+/// A row of the rate table file.
+#[derive(Deserialize)]
+struct RateRecord {
+    from: Currency,
+    to: Currency,
+    rate: Rate,
+}
+
+/// Exchange rates between pairs of currencies, used by `convert` events.
+///
+/// Rates only apply in the direction given: converting back needs a rate of its own.
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct RateTable {
+    rates: HashMap<(Currency, Currency), Rate>,
+}
+
+impl RateTable {
+    pub fn with_rate(mut self, from: Currency, to: Currency, rate: Rate) -> Self {
+        self.rates.insert((from, to), rate);
+        self
+    }
+
+    /// The rate for converting `from` into `to`, if the table has one.
+    pub fn get(&self, from: Currency, to: Currency) -> Option<Rate> {
+        self.rates.get(&(from, to)).copied()
+    }
+
+    /// Reads a CSV table with `from`, `to` and `rate` columns, e.g. `EUR,USD,1.0825`.
+    pub fn read<R: Read>(input: R) -> Result<Self, RatesError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        let mut table = RateTable::default();
+        for record in reader.deserialize() {
+            let RateRecord { from, to, rate } = record?;
+            if from == to {
+                return Err(RatesError::SameCurrency(from));
+            }
+            if table.rates.insert((from, to), rate).is_some() {
+                return Err(RatesError::DuplicateRate { from, to });
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RatesError> {
+        Self::read(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rate(s: &str) -> Rate {
+        s.parse().unwrap()
+    }
+
+    fn convert(units: i64, rate_text: &str, rounding: RoundingMode) -> i64 {
+        rate(rate_text)
+            .convert(Amount::from_units(units), rounding)
+            .unwrap()
+            .units()
+    }
+
+    #[test]
+    fn parses_exact_rates() {
+        assert_eq!(rate("1.0825").to_string(), "1.0825");
+        assert_eq!(rate("0.000000000001").to_string(), "0.000000000001");
+        assert_eq!(rate("2").to_string(), "2");
+        for invalid in ["", ".", "0", "0.0", "-1", "1e3", "0.0000000000001"] {
+            assert!(invalid.parse::<Rate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn converts_with_each_rounding_mode() {
+        // 1.0001 * 0.5 = 0.50005, exactly half a unit
+        let cases = [
+            (RoundingMode::HalfEven, 5_000, -5_000),
+            (RoundingMode::HalfUp, 5_001, -5_001),
+            (RoundingMode::Down, 5_000, -5_000),
+            (RoundingMode::Up, 5_001, -5_001),
+        ];
+        for (rounding, positive, negative) in cases {
+            assert_eq!(convert(10_001, "0.5", rounding), positive, "{:?}", rounding);
+            assert_eq!(
+                convert(-10_001, "0.5", rounding),
+                negative,
+                "{:?}",
+                rounding
+            );
+        }
+        // 1.0003 * 0.5 = 0.50015, whose even neighbour is above it
+        assert_eq!(convert(10_003, "0.5", RoundingMode::HalfEven), 5_002);
+        // 1.0000 * 0.33336 = 0.33336, nearer the amount above
+        assert_eq!(convert(10_000, "0.33336", RoundingMode::HalfEven), 3_334);
+        assert_eq!(convert(10_000, "0.33336", RoundingMode::Down), 3_333);
+        assert_eq!(
+            rate("1000").convert(Amount::from_units(i64::MAX), RoundingMode::Down),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_rate_table() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let table = RateTable::read(&b"from, to, rate\nEUR, USD, 1.0825\n"[..]).unwrap();
+        assert_eq!(table.get(eur, usd), Some(rate("1.0825")));
+        assert_eq!(table.get(usd, eur), None);
+
+        let duplicate = RateTable::read(&b"from,to,rate\nEUR,USD,1.08\neur,usd,1.09\n"[..]);
+        assert!(matches!(
+            duplicate,
+            Err(RatesError::DuplicateRate { from, to }) if from == eur && to == usd
+        ));
+        assert!(matches!(
+            RateTable::read(&b"from,to,rate\nEUR,USD,-1\n"[..]),
+            Err(RatesError::Malformed(_))
+        ));
+        assert!(matches!(
+            RateTable::read(&b"from,to,rate\nEUR,eur,1\n"[..]),
+            Err(RatesError::SameCurrency(currency)) if currency == eur
+        ));
+    }
+}
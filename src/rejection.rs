@@ -0,0 +1,246 @@
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::event::{Event, EventType};
+use crate::input::{ParseError, Row};
+use crate::logger::Fields;
+use crate::processor::ProcessorError;
+
+/// Code of rejections for rows which were read but could not be parsed into an event.
+pub const PARSE_ERROR: &str = "parse_error";
+/// Code of rejections for rows which could not be read at all, e.g. with too many fields.
+pub const MALFORMED_ROW: &str = "malformed_row";
+
+/// A structured record of an input row which was not applied to the store.
+///
+/// For rows which could not be parsed, `client`, `tx` and `event_type` are filled in from the
+/// raw row where their own columns are valid.
+#[derive(Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct Rejection {
+    pub line: Option<u64>,
+    pub row: String,
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    #[serde(rename = "type")]
+    pub event_type: Option<EventType>,
+    /// `ProcessorError::code` for rejected events, or `PARSE_ERROR` / `MALFORMED_ROW`.
+    pub code: String,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn fields(&self) -> Fields {
+        Fields {
+            line: self.line,
+            client: self.client,
+            tx: self.tx,
+            event_type: self.event_type,
+        }
+    }
+
+    pub(crate) fn processor_error(row: &Row, event: &Event, error: &ProcessorError) -> Self {
+        Self {
+            line: row.raw.line(),
+            row: row.raw.text(),
+            ..Self::rejected_event(event, error)
+        }
+    }
+
+    /// A rejection for an event which was not read from an input, so has no line or raw row.
+    pub(crate) fn rejected_event(event: &Event, error: &ProcessorError) -> Self {
+        Self {
+            line: None,
+            row: String::new(),
+            client: Some(event.client()),
+            tx: Some(event.tx()),
+            event_type: Some(event.event_type()),
+            code: error.code().to_owned(),
+            message: error.to_string(),
+        }
+    }
+
+    pub(crate) fn parse_error(row: &Row, error: &ParseError) -> Self {
+        let field = |name: &str| row.raw.field(name);
+        Self {
+            line: row.raw.line(),
+            row: row.raw.text(),
+            client: field("client").and_then(|client| client.parse().ok()),
+            tx: field("tx").and_then(|tx| tx.parse().ok()),
+            event_type: field("type").and_then(|event_type| event_type.parse().ok()),
+            code: PARSE_ERROR.to_owned(),
+            message: error.to_string(),
+        }
+    }
+
+    pub(crate) fn malformed_row(error: &csv::Error) -> Self {
+        Self {
+            line: error.position().map(|position| position.line()),
+            row: String::new(),
+            client: None,
+            tx: None,
+            event_type: None,
+            code: MALFORMED_ROW.to_owned(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Destination of the rejection report.
+pub trait RejectionSink {
+    fn write(&mut self, rejection: &Rejection) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes rejections as CSV, with a header row.
+pub struct CsvRejectionSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvRejectionSink<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(output),
+        }
+    }
+}
+
+impl<W: Write> RejectionSink for CsvRejectionSink<W> {
+    fn write(&mut self, rejection: &Rejection) -> io::Result<()> {
+        Ok(self.writer.serialize(rejection)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes rejections as JSON Lines, one object per rejection.
+pub struct JsonLinesRejectionSink<W: Write> {
+    output: W,
+}
+
+impl<W: Write> JsonLinesRejectionSink<W> {
+    pub fn new(output: W) -> Self {
+        Self { output }
+    }
+}
+
+impl<W: Write> RejectionSink for JsonLinesRejectionSink<W> {
+    fn write(&mut self, rejection: &Rejection) -> io::Result<()> {
+        serde_json::to_writer(&mut self.output, rejection)?;
+        self.output.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Collects rejections in memory.
+impl RejectionSink for Vec<Rejection> {
+    fn write(&mut self, rejection: &Rejection) -> io::Result<()> {
+        self.push(rejection.clone());
+        Ok(())
+    }
+}
+
+/// Shares a sink with a runner, e.g. to read the rejections it collected after a run.
+impl<T: RejectionSink> RejectionSink for Arc<Mutex<T>> {
+    fn write(&mut self, rejection: &Rejection) -> io::Result<()> {
+        self.lock()
+            .map_err(|_| io::Error::other("Rejection sink poisoned"))?
+            .write(rejection)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock()
+            .map_err(|_| io::Error::other("Rejection sink poisoned"))?
+            .flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::InputFormat;
+    use crate::input::{Input, InputRows};
+
+    fn rejection() -> Rejection {
+        Rejection {
+            line: Some(3),
+            row: "withdrawal,1,2,5.0".to_owned(),
+            client: Some(1),
+            tx: Some(2),
+            event_type: Some(EventType::Withdrawal),
+            code: ProcessorError::WithdrawalAboveBalance.code().to_owned(),
+            message: ProcessorError::WithdrawalAboveBalance.to_string(),
+        }
+    }
+
+    #[test]
+    fn csv_sink_quotes_raw_row() {
+        let mut sink = CsvRejectionSink::new(Vec::new());
+        sink.write(&rejection()).unwrap();
+        sink.flush().unwrap();
+        let output = String::from_utf8(sink.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "line,row,client,tx,type,code,message\n\
+             3,\"withdrawal,1,2,5.0\",1,2,withdrawal,withdrawal_above_balance,\
+             Withdrawal exceeds client withdrawable (free) balance\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_sink_writes_one_object_per_line() {
+        let mut sink = JsonLinesRejectionSink::new(Vec::new());
+        sink.write(&rejection()).unwrap();
+        sink.write(&rejection()).unwrap();
+        let output = String::from_utf8(sink.output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "{\"line\":3,\"row\":\"withdrawal,1,2,5.0\",\"client\":1,\"tx\":2,\
+             \"type\":\"withdrawal\",\"code\":\"withdrawal_above_balance\",\
+             \"message\":\"Withdrawal exceeds client withdrawable (free) balance\"}"
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_error_recovers_valid_columns() {
+        let input = "type, client, tx, amount\ndeposit, 7, 12,\n";
+        let mut rows = InputRows::new(vec![Input::from_reader(input.as_bytes())]);
+        let row = rows.next_row().await.unwrap().unwrap().unwrap();
+        let error = row.event.as_ref().unwrap_err();
+        let rejection = Rejection::parse_error(&row, error);
+        assert_eq!(rejection.line, Some(2));
+        assert_eq!(rejection.row, "deposit,7,12,");
+        assert_eq!(rejection.client, Some(7));
+        assert_eq!(rejection.tx, Some(12));
+        assert_eq!(rejection.event_type, Some(EventType::Deposit));
+        assert_eq!(rejection.code, PARSE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn parse_error_recovers_valid_json_fields() {
+        let input = "{\"type\": \"deposit\", \"client\": 7, \"tx\": \"twelve\"}\n";
+        let input = Input::from_reader(input.as_bytes()).with_format(InputFormat::JsonLines);
+        let mut rows = InputRows::new(vec![input]);
+        let row = rows.next_row().await.unwrap().unwrap().unwrap();
+        let error = row.event.as_ref().unwrap_err();
+        let rejection = Rejection::parse_error(&row, error);
+        assert_eq!(rejection.line, Some(1));
+        assert_eq!(
+            rejection.row,
+            "{\"type\": \"deposit\", \"client\": 7, \"tx\": \"twelve\"}"
+        );
+        assert_eq!(rejection.client, Some(7));
+        assert_eq!(rejection.tx, None);
+        assert_eq!(rejection.event_type, Some(EventType::Deposit));
+    }
+}
@@ -1,116 +1,1872 @@
 use async_trait::async_trait;
-use serde::Deserialize;
-use serde::{ser::SerializeStruct, Serialize, Serializer};
-use std::io;
+use futures_core::Stream;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::future;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinError;
 
-use crate::logger::Logger;
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::event::{Event, EventType};
+use crate::fees::FeesError;
+use crate::format::{Encoder, OutputFormat};
+use crate::input::{Input, InputRows, Row};
+use crate::journal::{Journal, JournalEntry, JournalError};
+use crate::limits::LimitsError;
+use crate::logger::{Fields, Logger};
+use crate::policy::Policy;
 use crate::processor::{
-    ChargebackProcessor, DepositProcessor, DisputeProcessor, Processor, ResolveProcessor,
-    WithdrawalProcessor,
+    AdjustmentProcessor, ChargebackProcessor, ConvertProcessor, CounterpartyProcessor,
+    DepositProcessor, DisputeProcessor, LockProcessor, Processor, ProcessorError, ResolveProcessor,
+    TransferChargebackProcessor, TransferDisputeProcessor, TransferProcessor,
+    TransferResolveProcessor, UnlockProcessor, WithdrawalProcessor,
+};
+use crate::rates::RatesError;
+use crate::rejection::{Rejection, RejectionSink};
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::store::{
+    Balance, Changeset, Client, ClientOrder, Credit, DisputeState, Store, StoreError, Transaction,
+    TransactionKind,
 };
-use crate::store::{Client, Store, StoreError};
 
 #[derive(Error, Debug)]
 pub enum RunnerError {
     #[error("{0}")]
     StoreError(#[from] StoreError),
-    #[error("Input file could not be opened")]
-    FileError,
+    #[error("Input file {} could not be opened: {source}", path.display())]
+    FileError { path: PathBuf, source: io::Error },
+    #[error("Input {input} could not be read: {source}")]
+    InputError { input: String, source: io::Error },
+    #[error("Output could not be written: {0}")]
+    OutputError(#[from] io::Error),
+    #[error("{0}")]
+    JournalError(#[from] JournalError),
+    #[error("{0}")]
+    SnapshotError(#[from] SnapshotError),
+    #[error("{0}")]
+    RatesError(#[from] RatesError),
+    #[error("{0}")]
+    FeesError(#[from] FeesError),
+    #[error("{0}")]
+    LimitsError(#[from] LimitsError),
+    #[error("Worker task failed: {0}")]
+    WorkerError(#[from] JoinError),
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum EventType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
+/// Statistics about a run, including counts of input rows which were not applied to the store.
+///
+/// Amount totals are kept by currency, and saturate at the largest amount rather than overflow.
+#[derive(Serialize, Default, PartialEq, Debug, Clone)]
+pub struct RunSummary {
+    /// Rows read from the inputs, or events passed in.
+    pub rows_read: u64,
+    /// Rows which could not be parsed into an event.
+    pub parse_failures: usize,
+    /// Events which were parsed but rejected by their processor.
+    pub rejected: usize,
+    /// Events which were applied to the store.
+    pub applied: usize,
+    /// Parsed events, by type.
+    pub events: BTreeMap<EventType, usize>,
+    /// Rejected events, by `ProcessorError::code`.
+    pub rejections: BTreeMap<&'static str, usize>,
+    /// Sum of the applied deposits.
+    pub deposited: BTreeMap<Currency, Amount>,
+    /// Sum of the applied withdrawals.
+    pub withdrawn: BTreeMap<Currency, Amount>,
+    /// Sum of the applied transfers.
+    pub transferred: BTreeMap<Currency, Amount>,
+    /// Sum of the charged back deposits, withdrawals and transfers.
+    pub charged_back: BTreeMap<Currency, Amount>,
+    /// Sum of the fees credited to the house account.
+    pub fees: BTreeMap<Currency, Amount>,
+    /// Accounts which were locked once the run was complete.
+    pub locked_accounts: usize,
+    /// Wall-clock time taken by the run, excluding writing the reports.
+    #[serde(rename = "elapsed_seconds", serialize_with = "seconds")]
+    pub elapsed: Duration,
+    /// Rows read per second of `elapsed`.
+    pub rows_per_second: f64,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct Event {
-    #[serde(rename = "type")]
-    pub event_type: EventType,
-    pub client: u16,
-    pub tx: u32,
-    pub amount: Option<f64>,
+impl RunSummary {
+    pub fn is_clean(&self) -> bool {
+        self.parse_failures == 0 && self.rejected == 0
+    }
+
+    fn record_event(&mut self, event: &Event) {
+        *self.events.entry(event.event_type()).or_default() += 1;
+    }
+
+    fn record_applied(&mut self, event: &Event, entry: &JournalEntry) {
+        self.applied += 1;
+        for credit in &entry.credits {
+            let total = self.fees.entry(credit.currency).or_default();
+            *total = total.saturating_add(credit.amount);
+        }
+        let totals = match event {
+            Event::Deposit(_) => &mut self.deposited,
+            Event::Withdrawal(_) => &mut self.withdrawn,
+            Event::Transfer(_) => &mut self.transferred,
+            Event::Chargeback(_) => &mut self.charged_back,
+            _ => return,
+        };
+        for change in &entry.transactions {
+            // Chargebacks move what the client received, net of any deposit fee
+            let amount = match event {
+                Event::Chargeback(_) => change.after.disputed_amount(),
+                _ => None,
+            }
+            .unwrap_or(change.after.amount);
+            let total = totals.entry(change.after.currency).or_default();
+            *total = total.saturating_add(amount.saturating_abs());
+        }
+    }
+
+    fn record_rejection(&mut self, error: &ProcessorError) {
+        self.rejected += 1;
+        *self.rejections.entry(error.code()).or_default() += 1;
+    }
+
+    /// Adds the counts and totals of a summary covering other events of the same run.
+    fn merge(&mut self, other: RunSummary) {
+        self.rows_read += other.rows_read;
+        self.parse_failures += other.parse_failures;
+        self.rejected += other.rejected;
+        self.applied += other.applied;
+        for (event_type, count) in other.events {
+            *self.events.entry(event_type).or_default() += count;
+        }
+        for (code, count) in other.rejections {
+            *self.rejections.entry(code).or_default() += count;
+        }
+        for (totals, other) in [
+            (&mut self.deposited, other.deposited),
+            (&mut self.withdrawn, other.withdrawn),
+            (&mut self.transferred, other.transferred),
+            (&mut self.charged_back, other.charged_back),
+            (&mut self.fees, other.fees),
+        ] {
+            for (currency, amount) in other {
+                let total = totals.entry(currency).or_default();
+                *total = total.saturating_add(amount);
+            }
+        }
+    }
+
+    /// Records the final state of `store` and the time taken since `started`.
+    fn finish<S: Store>(&mut self, store: &S, started: Instant) -> Result<(), StoreError> {
+        self.locked_accounts = store
+            .dump_clients()?
+            .iter()
+            .filter(|client| client.locked)
+            .count();
+        self.elapsed = started.elapsed();
+        self.rows_per_second = match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.rows_read as f64 / secs,
+            _ => 0.0,
+        };
+        Ok(())
+    }
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Finished processing: {} rows could not be parsed, {} events were rejected",
+            self.parse_failures, self.rejected
+        )
+    }
+}
+
+/// The final balances left by processing events, and the events which were not applied.
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct RunOutcome {
+    /// Every client in the store, ordered by client ID.
+    pub clients: Vec<Client>,
+    pub summary: RunSummary,
+    /// Every rejected event, in the order it was processed.
+    pub rejections: Vec<Rejection>,
 }
 
 #[async_trait]
 pub trait Runner {
-    async fn run(&mut self) -> Result<(), RunnerError>;
+    async fn run(&mut self) -> Result<RunSummary, RunnerError>;
+}
+
+type Output = Box<dyn Write + Send>;
+
+type Rejections = Box<dyn RejectionSink + Send>;
+type BoxedJournal = Box<dyn Journal + Send>;
+
+/// Where a run reads its events from and writes its reports to, and the policy it applies,
+/// shared by every runner.
+pub struct RunConfig {
+    inputs: Vec<Input>,
+    output: Option<Output>,
+    output_format: OutputFormat,
+    order: ClientOrder,
+    transaction_output: Option<(Output, OutputFormat)>,
+    rejections: Option<Rejections>,
+    journal: Option<BoxedJournal>,
+    snapshot: Option<Snapshot>,
+    snapshot_output: Option<Output>,
+    policy: Policy,
+}
+
+impl RunConfig {
+    pub fn new(input: impl Into<Input>) -> Self {
+        Self {
+            inputs: vec![input.into()],
+            output: None,
+            output_format: OutputFormat::default(),
+            order: ClientOrder::default(),
+            transaction_output: None,
+            rejections: None,
+            journal: None,
+            snapshot: None,
+            snapshot_output: None,
+            policy: Policy::default(),
+        }
+    }
+
+    /// Writes the reports asked for once the run is complete.
+    fn write_reports<S: Store, L: Logger + ?Sized>(
+        &mut self,
+        store: &S,
+        logger: &L,
+    ) -> Result<(), RunnerError> {
+        if let Some(output) = &mut self.output {
+            write_clients(store, output, self.output_format, self.order, logger)?;
+        }
+        if let Some((output, format)) = &mut self.transaction_output {
+            write_transactions(store, output, *format, None)?;
+        }
+        if let Some(output) = &mut self.snapshot_output {
+            Snapshot::capture(store)?.write(output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builder methods for a `RunConfig`, or a runner holding one.
+pub trait Configure: Sized {
+    fn config_mut(&mut self) -> &mut RunConfig;
+
+    /// Adds an input to be processed after the previous ones, against the same store.
+    fn add_input(mut self, input: impl Into<Input>) -> Self {
+        self.config_mut().inputs.push(input.into());
+        self
+    }
+
+    /// Writes the client report to `output` once the run is complete.
+    fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.config_mut().output = Some(Box::new(output));
+        self
+    }
+
+    /// Writes the client report in `format` instead of CSV.
+    fn with_output_format(mut self, format: OutputFormat) -> Self {
+        self.config_mut().output_format = format;
+        self
+    }
+
+    /// Orders the client report by `order` instead of by client ID.
+    fn with_client_order(mut self, order: ClientOrder) -> Self {
+        self.config_mut().order = order;
+        self
+    }
+
+    /// Also writes the final transaction ledger to `output` in `format`, after the client report.
+    fn with_transaction_output(
+        mut self,
+        output: impl Write + Send + 'static,
+        format: OutputFormat,
+    ) -> Self {
+        self.config_mut().transaction_output = Some((Box::new(output), format));
+        self
+    }
+
+    /// Reports every row which could not be parsed or was rejected to `rejections`.
+    fn with_rejections(mut self, rejections: impl RejectionSink + Send + 'static) -> Self {
+        self.config_mut().rejections = Some(Box::new(rejections));
+        self
+    }
+
+    /// Appends the state changes made by every applied event to `journal`.
+    fn with_journal(mut self, journal: impl Journal + Send + 'static) -> Self {
+        self.config_mut().journal = Some(Box::new(journal));
+        self
+    }
+
+    /// Restores `snapshot` into the store, which must be empty, before processing any events.
+    fn with_snapshot(mut self, snapshot: Snapshot) -> Self {
+        self.config_mut().snapshot = Some(snapshot);
+        self
+    }
+
+    /// Writes a snapshot of the store to `output` once the run is complete.
+    fn with_snapshot_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.config_mut().snapshot_output = Some(Box::new(output));
+        self
+    }
+
+    /// Processes events under `policy` instead of the default one.
+    fn with_policy(mut self, policy: Policy) -> Self {
+        self.config_mut().policy = policy;
+        self
+    }
 }
 
-pub struct CsvSingleProcessRunner<S: Store + Send + Sync, L: Logger> {
-    input_file: String,
+impl Configure for RunConfig {
+    fn config_mut(&mut self) -> &mut RunConfig {
+        self
+    }
+}
+
+pub struct CsvSingleProcessRunner<S: Store + Send, L: Logger> {
+    config: RunConfig,
     store: S,
     logger: L,
 }
 
-impl<S: Store + Default + Send + Sync, L: Logger + Default> CsvSingleProcessRunner<S, L> {
-    pub fn new(input_file: &str) -> Self {
+impl<S: Store + Default + Send, L: Logger + Default> CsvSingleProcessRunner<S, L> {
+    pub fn new(input: impl Into<Input>) -> Self {
+        Self::with_store(input, S::default())
+    }
+}
+
+impl<S: Store + Send, L: Logger + Default> CsvSingleProcessRunner<S, L> {
+    /// Processes events against an existing store, e.g. one holding a previous run's balances.
+    pub fn with_store(input: impl Into<Input>, store: S) -> Self {
+        Self::with_config(RunConfig::new(input), store)
+    }
+
+    pub fn with_config(config: RunConfig, store: S) -> Self {
         Self {
-            input_file: input_file.to_owned(),
-            store: S::default(),
+            config,
+            store,
             logger: L::default(),
         }
     }
 }
 
+impl<S: Store + Send, L: Logger> CsvSingleProcessRunner<S, L> {
+    pub fn with_logger(mut self, logger: L) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+impl<S: Store + Send, L: Logger> Configure for CsvSingleProcessRunner<S, L> {
+    fn config_mut(&mut self) -> &mut RunConfig {
+        &mut self.config
+    }
+}
+
 #[async_trait]
-impl<S: Store + Send + Sync, L: Logger + Send + Sync> Runner for CsvSingleProcessRunner<S, L> {
-    async fn run(&mut self) -> Result<(), RunnerError> {
-        let mut rdr = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_path(&self.input_file)
-            .or(Err(RunnerError::FileError))?;
-        for result in rdr.deserialize::<Event>() {
-            if let Some(event) = result.as_ref().ok() {
-                let maybe_tx = self.store.get_transaction(event.tx);
-                let maybe_client = self.store.get_client(event.client);
-                let result = match event.event_type {
-                    EventType::Deposit => DepositProcessor::process_event,
-                    EventType::Withdrawal => WithdrawalProcessor::process_event,
-                    EventType::Dispute => DisputeProcessor::process_event,
-                    EventType::Resolve => ResolveProcessor::process_event,
-                    EventType::Chargeback => ChargebackProcessor::process_event,
-                }(maybe_tx, maybe_client, event)
-                .await;
-                match result {
-                    Err(e) => self.logger.error(e.to_string()),
-                    Ok((client, transaction)) => {
-                        self.store.set_transaction(transaction)?;
-                        self.store.set_client(client)?;
+impl<S: Store + Send, L: Logger + Send + Sync> Runner for CsvSingleProcessRunner<S, L> {
+    async fn run(&mut self) -> Result<RunSummary, RunnerError> {
+        let started = Instant::now();
+        if let Some(snapshot) = self.config.snapshot.take() {
+            snapshot.restore(&mut self.store)?;
+        }
+        let mut summary = RunSummary::default();
+        let mut rows = InputRows::new(std::mem::take(&mut self.config.inputs));
+        while let Some(result) = rows.next_row().await? {
+            summary.rows_read += 1;
+            log_progress(&self.logger, summary.rows_read);
+            let rejection = match result {
+                Err(e) => {
+                    summary.parse_failures += 1;
+                    Rejection::malformed_row(&e)
+                }
+                Ok(row) => match &row.event {
+                    Err(e) => {
+                        summary.parse_failures += 1;
+                        Rejection::parse_error(&row, e)
+                    }
+                    Ok(event) => {
+                        summary.record_event(event);
+                        match process_event(
+                            &mut self.store,
+                            event,
+                            &self.config.policy,
+                            &self.logger,
+                        )
+                        .await
+                        {
+                            Ok(entry) => {
+                                summary.record_applied(event, &entry);
+                                if let Some(journal) = &mut self.config.journal {
+                                    journal.append(&entry)?;
+                                }
+                                continue;
+                            }
+                            Err(ProcessorError::StoreError(e)) => return Err(e.into()),
+                            Err(e) => {
+                                summary.record_rejection(&e);
+                                Rejection::processor_error(&row, event, &e)
+                            }
+                        }
                     }
+                },
+            };
+            log_rejection(&self.logger, &rejection);
+            if let Some(rejections) = &mut self.config.rejections {
+                rejections.write(&rejection)?;
+            }
+        }
+
+        self.store.flush()?;
+        if let Some(journal) = &mut self.config.journal {
+            journal.flush()?;
+        }
+        if let Some(rejections) = &mut self.config.rejections {
+            rejections.flush()?;
+        }
+        summary.finish(&self.store, started)?;
+        self.logger
+            .info(Fields::default(), format_args!("{}", summary));
+        self.config.write_reports(&self.store, &self.logger)?;
+        Ok(summary)
+    }
+}
+
+/// The client other than the event's own whose account the event changes, if any: the
+/// destination of a transfer, for the transfer itself and for disputes of it.
+fn counterparty(event: &Event, maybe_tx: Option<&Transaction>) -> Option<u16> {
+    match (event, maybe_tx) {
+        (Event::Transfer(e), _) => Some(e.destination),
+        (Event::Dispute(_) | Event::Resolve(_) | Event::Chargeback(_), Some(tx))
+            if tx.kind == TransactionKind::Transfer =>
+        {
+            tx.counterparty
+        }
+        _ => None,
+    }
+}
+
+/// Runs a single event through its processor and applies the resulting changes to the store,
+/// returning them as a journal entry.
+///
+/// Store failures are returned as `ProcessorError::StoreError`, which callers should treat as
+/// fatal rather than as a rejected event.
+async fn process_event<S: Store + Send, L: Logger + Sync>(
+    store: &mut S,
+    event: &Event,
+    policy: &Policy,
+    logger: &L,
+) -> Result<JournalEntry, ProcessorError> {
+    let maybe_tx = store.get_transaction(event.tx())?;
+    let maybe_client = store.get_client(event.client())?;
+    let maybe_counterparty = match counterparty(event, maybe_tx.as_ref()) {
+        Some(id) => Some(store.get_client(id)?),
+        None => None,
+    };
+    // PRECONDITION: events for a client must not go back in time, for either client
+    for maybe_client in std::iter::once(&maybe_client).chain(maybe_counterparty.iter()) {
+        let last_event_at = maybe_client
+            .as_ref()
+            .and_then(|client| client.last_event_at);
+        if let (Some(timestamp), Some(last_event_at)) = (event.timestamp(), last_event_at) {
+            if timestamp < last_event_at {
+                return Err(ProcessorError::TimestampOutOfOrder);
+            }
+        }
+    }
+    let (tx, client) = (maybe_tx.clone(), maybe_client.clone());
+    let (mut client, mut other, tx) = match maybe_counterparty.clone() {
+        Some(other) => {
+            let (client, other, tx) = match event {
+                Event::Transfer(e) => {
+                    TransferProcessor::process_event(tx, client, other, e, policy, logger).await
+                }
+                Event::Dispute(e) => {
+                    TransferDisputeProcessor::process_event(tx, client, other, e, policy, logger)
+                        .await
+                }
+                Event::Resolve(e) => {
+                    TransferResolveProcessor::process_event(tx, client, other, e, policy, logger)
+                        .await
+                }
+                Event::Chargeback(e) => {
+                    TransferChargebackProcessor::process_event(tx, client, other, e, policy, logger)
+                        .await
+                }
+                _ => unreachable!("only transfers and their disputes have a counterparty"),
+            }?;
+            (client, Some(other), tx)
+        }
+        None => {
+            let (client, tx) = match event {
+                Event::Deposit(e) => {
+                    DepositProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Withdrawal(e) => {
+                    WithdrawalProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Dispute(e) => {
+                    DisputeProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Resolve(e) => {
+                    ResolveProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Chargeback(e) => {
+                    ChargebackProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Lock(e) => LockProcessor::process_event(tx, client, e, policy, logger).await,
+                Event::Unlock(e) => {
+                    UnlockProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Adjustment(e) => {
+                    AdjustmentProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Convert(e) => {
+                    ConvertProcessor::process_event(tx, client, e, policy, logger).await
+                }
+                Event::Transfer(_) => unreachable!("transfers always have a counterparty"),
+            }?;
+            (client, None, tx)
+        }
+    };
+    if event.timestamp().is_some() {
+        client.last_event_at = event.timestamp();
+        if let Some(other) = &mut other {
+            other.last_event_at = event.timestamp();
+        }
+    }
+    // A fee is paid to the house account once, by the event which created the transaction
+    let credit = match (&maybe_tx, tx.fee) {
+        (None, Some(fee)) => Some(Credit {
+            client: fee.account,
+            currency: tx.currency,
+            amount: fee.amount,
+        }),
+        _ => None,
+    };
+    let mut clients = vec![client.clone()];
+    clients.extend(other.clone());
+    store.apply(Changeset {
+        clients,
+        transactions: vec![tx.clone()],
+        credits: credit.into_iter().collect(),
+    })?;
+    let mut entry = JournalEntry::new(event, (maybe_client, maybe_tx), (client, tx));
+    if let (Some(before), Some(after)) = (maybe_counterparty, other) {
+        entry = entry.with_client(before, after);
+    }
+    Ok(match credit {
+        Some(credit) => entry.with_credit(credit),
+        None => entry,
+    })
+}
+
+/// Processes `events` in order against `store`, returning the resulting balances rather than
+/// writing a report.
+///
+/// Only store failures are returned as errors; rejected events are collected in the outcome.
+pub async fn process_events<S, L, I>(
+    events: I,
+    store: &mut S,
+    policy: &Policy,
+    logger: &L,
+) -> Result<RunOutcome, RunnerError>
+where
+    S: Store + Send,
+    L: Logger + Sync,
+    I: IntoIterator<Item = Event>,
+{
+    let started = Instant::now();
+    let mut outcome = RunOutcome::default();
+    for event in events {
+        apply_event(store, &event, policy, logger, &mut outcome).await?;
+    }
+    finish_outcome(store, outcome, started)
+}
+
+/// Like `process_events`, for events which arrive asynchronously, e.g. from a message queue.
+pub async fn process_event_stream<S, L, E>(
+    mut events: E,
+    store: &mut S,
+    policy: &Policy,
+    logger: &L,
+) -> Result<RunOutcome, RunnerError>
+where
+    S: Store + Send,
+    L: Logger + Sync,
+    E: Stream<Item = Event> + Unpin,
+{
+    let started = Instant::now();
+    let mut outcome = RunOutcome::default();
+    while let Some(event) = future::poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+        apply_event(store, &event, policy, logger, &mut outcome).await?;
+    }
+    finish_outcome(store, outcome, started)
+}
+
+async fn apply_event<S: Store + Send, L: Logger + Sync>(
+    store: &mut S,
+    event: &Event,
+    policy: &Policy,
+    logger: &L,
+    outcome: &mut RunOutcome,
+) -> Result<(), RunnerError> {
+    outcome.summary.rows_read += 1;
+    outcome.summary.record_event(event);
+    match process_event(store, event, policy, logger).await {
+        Ok(entry) => {
+            outcome.summary.record_applied(event, &entry);
+            Ok(())
+        }
+        Err(ProcessorError::StoreError(e)) => Err(e.into()),
+        Err(e) => {
+            outcome.summary.record_rejection(&e);
+            let rejection = Rejection::rejected_event(event, &e);
+            log_rejection(logger, &rejection);
+            outcome.rejections.push(rejection);
+            Ok(())
+        }
+    }
+}
+
+fn finish_outcome<S: Store>(
+    store: &mut S,
+    mut outcome: RunOutcome,
+    started: Instant,
+) -> Result<RunOutcome, RunnerError> {
+    store.flush()?;
+    outcome.summary.finish(store, started)?;
+    outcome.clients = store.dump_clients_sorted(ClientOrder::Id)?;
+    Ok(outcome)
+}
+
+/// Writes the client report for every client in `store` in `format`, in `order`, with one row
+/// per currency the client holds, in currency order.
+///
+/// If every balance is in `Currency::UNSPECIFIED`, the `currency` column is left out, so reports
+/// on single-currency inputs keep their original columns.
+///
+/// Balances which cannot be reported (e.g. whose total overflows) are logged and skipped.
+pub fn write_clients<S: Store, W: Write, L: Logger + ?Sized>(
+    store: &S,
+    output: W,
+    format: OutputFormat,
+    order: ClientOrder,
+    logger: &L,
+) -> Result<(), RunnerError> {
+    let mut encoder = Encoder::new(format, output);
+    let clients = store.dump_clients_sorted(order)?;
+    let single_currency = clients.iter().all(|client| {
+        client
+            .balances
+            .keys()
+            .all(|&currency| currency == Currency::UNSPECIFIED)
+    });
+    for client in clients {
+        for (&currency, balance) in &client.balances {
+            let currency = (!single_currency).then_some(currency);
+            match ClientReport::new(&client, currency, balance) {
+                Some(report) => encoder.encode(&report)?,
+                None => {
+                    let fields = Fields {
+                        client: Some(client.id),
+                        ..Fields::default()
+                    };
+                    logger.error(
+                        fields,
+                        format_args!(
+                            "Client total {} balance overflows",
+                            currency.unwrap_or_default()
+                        ),
+                    );
                 }
-            } else if let Some(e) = result.err() {
-                self.logger.error(e.to_string())
             }
         }
+    }
+    encoder.finish()?;
+    Ok(())
+}
 
-        let mut wtr = csv::Writer::from_writer(io::stdout());
-        for client in self.store.dump_clients() {
-            if let Err(e) = wtr.serialize(client) {
-                self.logger.error(e.to_string());
+/// Writes the transaction ledger in `store` in `format`, ordered by transaction ID, optionally
+/// only the transactions of `client`.
+pub fn write_transactions<S: Store, W: Write>(
+    store: &S,
+    output: W,
+    format: OutputFormat,
+    client: Option<u16>,
+) -> Result<(), RunnerError> {
+    let mut transactions = store.dump_transactions()?;
+    transactions.sort_by_key(|tx| tx.id);
+    let mut encoder = Encoder::new(format, output);
+    for tx in &transactions {
+        let report = TransactionReport::from(tx);
+        if client.is_none_or(|client| report.client == client) {
+            encoder.encode(&report)?;
+        }
+        // Each fee is a separate entry against the house account
+        if let Some(fee) = TransactionReport::fee(tx) {
+            if client.is_none_or(|client| fee.client == client) {
+                encoder.encode(&fee)?;
             }
         }
-        Ok(())
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Reports progress every this many input rows.
+const PROGRESS_INTERVAL: u64 = 100_000;
+
+fn log_progress<L: Logger + ?Sized>(logger: &L, rows_read: u64) {
+    if rows_read.is_multiple_of(PROGRESS_INTERVAL) {
+        logger.info(Fields::default(), format_args!("Read {} rows", rows_read));
+    }
+}
+
+fn log_rejection<L: Logger + ?Sized>(logger: &L, rejection: &Rejection) {
+    logger.warn(rejection.fields(), format_args!("{}", rejection.message));
+}
+
+const SHARD_QUEUE_SIZE: usize = 1024;
+
+/// Runner which partitions events by client across `workers` concurrent tasks.
+///
+/// Events for the same client always go to the same worker, so they are processed in input
+/// order; events for different clients may be processed in any order relative to each other.
+/// Events which change two clients' accounts, i.e. transfers and disputes of them, are processed
+/// by the reading task once every worker has caught up, so both clients see them in input order.
+/// So are the house account's own events when fees are charged, as every worker credits it, and
+/// events whose transaction ID an earlier event on another worker used, so that the transaction
+/// is created once and disputes of it are rejected as they would be by a single process.
+///
+/// Rows rejected by different workers may be reported in a different order than the input, and
+/// journal entries for different clients may be interleaved differently than in the input.
+pub struct ShardedRunner<S: Store + Clone + Send + 'static, L: Logger + Send + Sync + 'static> {
+    config: RunConfig,
+    workers: usize,
+    store: S,
+    logger: Arc<L>,
+}
+
+impl<S, L> ShardedRunner<S, L>
+where
+    S: Store + Clone + Default + Send + 'static,
+    L: Logger + Default + Send + Sync + 'static,
+{
+    pub fn new(input: impl Into<Input>, workers: usize) -> Self {
+        Self::with_store(input, workers, S::default())
+    }
+}
+
+impl<S, L> ShardedRunner<S, L>
+where
+    S: Store + Clone + Send + 'static,
+    L: Logger + Default + Send + Sync + 'static,
+{
+    /// `store` is cloned into every worker, so clones must share the same underlying state.
+    pub fn with_store(input: impl Into<Input>, workers: usize, store: S) -> Self {
+        Self::with_config(RunConfig::new(input), workers, store)
+    }
+
+    pub fn with_config(config: RunConfig, workers: usize, store: S) -> Self {
+        Self {
+            config,
+            workers: workers.max(1),
+            store,
+            logger: Arc::new(L::default()),
+        }
+    }
+}
+
+impl<S, L> ShardedRunner<S, L>
+where
+    S: Store + Clone + Send + 'static,
+    L: Logger + Send + Sync + 'static,
+{
+    pub fn with_logger(mut self, logger: L) -> Self {
+        self.logger = Arc::new(logger);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+impl<S, L> Configure for ShardedRunner<S, L>
+where
+    S: Store + Clone + Send + 'static,
+    L: Logger + Send + Sync + 'static,
+{
+    fn config_mut(&mut self) -> &mut RunConfig {
+        &mut self.config
+    }
+}
+
+/// Logs `rejection` and writes it to the shared rejection report, if any.
+fn report<L: Logger>(
+    logger: &L,
+    rejections: &Option<Arc<Mutex<Rejections>>>,
+    rejection: Rejection,
+) -> Result<(), RunnerError> {
+    log_rejection(logger, &rejection);
+    if let Some(rejections) = rejections {
+        lock(rejections)?.write(&rejection)?;
+    }
+    Ok(())
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> io::Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| io::Error::other("Lock poisoned by a failed worker"))
+}
+
+/// Work sent to a shard's worker.
+enum Job {
+    Process(Row),
+    /// Signals once every job sent before it has been processed.
+    Drain(oneshot::Sender<()>),
+}
+
+/// Waits for every worker to process the jobs already sent to it.
+///
+/// Returns `false` if a worker has stopped on an error, reported when it is joined.
+async fn drain(senders: &[mpsc::Sender<Job>]) -> bool {
+    let mut drained = Vec::with_capacity(senders.len());
+    for sender in senders {
+        let (done, wait) = oneshot::channel();
+        if sender.send(Job::Drain(done)).await.is_err() {
+            return false;
+        }
+        drained.push(wait);
+    }
+    for wait in drained {
+        if wait.await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Processes the event of a parsed `row`, recording the outcome in `summary` and reporting it.
+async fn process_row<S: Store + Send, L: Logger + Sync>(
+    store: &mut S,
+    row: &Row,
+    policy: &Policy,
+    logger: &L,
+    rejections: &Option<Arc<Mutex<Rejections>>>,
+    journal: &Option<Arc<Mutex<BoxedJournal>>>,
+    summary: &mut RunSummary,
+) -> Result<(), RunnerError> {
+    let event = row.event.as_ref().expect("only parsed rows are processed");
+    match process_event(store, event, policy, logger).await {
+        Err(ProcessorError::StoreError(e)) => return Err(RunnerError::from(e)),
+        Err(e) => {
+            summary.record_rejection(&e);
+            let rejection = Rejection::processor_error(row, event, &e);
+            report(logger, rejections, rejection)?;
+        }
+        Ok(entry) => {
+            summary.record_applied(event, &entry);
+            if let Some(journal) = journal {
+                lock(journal)?.append(&entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl<S, L> Runner for ShardedRunner<S, L>
+where
+    S: Store + Clone + Send + 'static,
+    L: Logger + Send + Sync + 'static,
+{
+    async fn run(&mut self) -> Result<RunSummary, RunnerError> {
+        let started = Instant::now();
+        if let Some(snapshot) = self.config.snapshot.take() {
+            snapshot.restore(&mut self.store)?;
+        }
+        let mut summary = RunSummary::default();
+        let mut rows = InputRows::new(std::mem::take(&mut self.config.inputs));
+        let rejections = self
+            .config
+            .rejections
+            .take()
+            .map(|r| Arc::new(Mutex::new(r)));
+        let journal = self.config.journal.take().map(|j| Arc::new(Mutex::new(j)));
+        // The shard of the first event with each transaction ID
+        let mut tx_shards: HashMap<u32, usize> = HashMap::new();
+
+        let mut senders = Vec::with_capacity(self.workers);
+        let mut handles = Vec::with_capacity(self.workers);
+        for _ in 0..self.workers {
+            let (sender, mut receiver) = mpsc::channel::<Job>(SHARD_QUEUE_SIZE);
+            let mut store = self.store.clone();
+            let logger = Arc::clone(&self.logger);
+            let rejections = rejections.clone();
+            let journal = journal.clone();
+            let policy = self.config.policy.clone();
+            handles.push(tokio::spawn(async move {
+                let mut summary = RunSummary::default();
+                while let Some(job) = receiver.recv().await {
+                    let row = match job {
+                        Job::Process(row) => row,
+                        Job::Drain(done) => {
+                            // The reader may have given up waiting after another worker failed
+                            let _ = done.send(());
+                            continue;
+                        }
+                    };
+                    process_row(
+                        &mut store,
+                        &row,
+                        &policy,
+                        logger.as_ref(),
+                        &rejections,
+                        &journal,
+                        &mut summary,
+                    )
+                    .await?;
+                }
+                store.flush()?;
+                Ok::<_, RunnerError>(summary)
+            }));
+            senders.push(sender);
+        }
+
+        while let Some(result) = rows.next_row().await? {
+            summary.rows_read += 1;
+            log_progress(self.logger.as_ref(), summary.rows_read);
+            match result {
+                Ok(row) => match &row.event {
+                    Ok(event) => {
+                        summary.record_event(event);
+                        let shard = usize::from(event.client()) % self.workers;
+                        // Another shard may not have applied its event with the same ID yet
+                        let tx_shard = *tx_shards.entry(event.tx()).or_insert(shard);
+                        let maybe_tx = match event {
+                            Event::Dispute(_) | Event::Resolve(_) | Event::Chargeback(_) => {
+                                self.store.get_transaction(event.tx())?
+                            }
+                            _ => None,
+                        };
+                        // Fees credit the house account from every worker, so events replacing
+                        // its record must not run alongside them
+                        let house_account = !self.config.policy.fees.is_empty()
+                            && event.client() == self.config.policy.house_account;
+                        if tx_shard != shard
+                            || house_account
+                            || counterparty(event, maybe_tx.as_ref()).is_some()
+                        {
+                            // Every earlier event it depends on must be applied first, and no
+                            // later ones are sent until this has been
+                            if !drain(&senders).await {
+                                break;
+                            }
+                            process_row(
+                                &mut self.store,
+                                &row,
+                                &self.config.policy,
+                                self.logger.as_ref(),
+                                &rejections,
+                                &journal,
+                                &mut summary,
+                            )
+                            .await?;
+                            continue;
+                        }
+                        if senders[shard].send(Job::Process(row)).await.is_err() {
+                            // The worker has stopped on an error, reported when joined below
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        summary.parse_failures += 1;
+                        let rejection = Rejection::parse_error(&row, e);
+                        report(self.logger.as_ref(), &rejections, rejection)?;
+                    }
+                },
+                Err(e) => {
+                    summary.parse_failures += 1;
+                    let rejection = Rejection::malformed_row(&e);
+                    report(self.logger.as_ref(), &rejections, rejection)?;
+                }
+            }
+        }
+        drop(senders);
+
+        for handle in handles {
+            summary.merge(handle.await??);
+        }
+        self.store.flush()?;
+        if let Some(journal) = &journal {
+            lock(journal)?.flush()?;
+        }
+        if let Some(rejections) = &rejections {
+            lock(rejections)?.flush()?;
+        }
+        summary.finish(&self.store, started)?;
+        self.logger
+            .info(Fields::default(), format_args!("{}", summary));
+        self.config
+            .write_reports(&self.store, self.logger.as_ref())?;
+        Ok(summary)
+    }
+}
+
+/// A client's balance in one currency as written to the client report, with its total.
+#[derive(Serialize)]
+struct ClientReport {
+    client: u16,
+    /// Left out of reports in which every balance is unspecified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+impl ClientReport {
+    /// Returns `None` if the total balance overflows.
+    fn new(client: &Client, currency: Option<Currency>, balance: &Balance) -> Option<Self> {
+        Some(Self {
+            client: client.id,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total()?,
+            locked: client.locked,
+        })
+    }
+}
+
+/// A transaction as written to the transaction ledger.
+#[derive(Serialize)]
+struct TransactionReport {
+    tx: u32,
+    client: u16,
+    /// The destination of a transfer, or the client which paid a fee.
+    counterparty: Option<u16>,
+    #[serde(rename = "type")]
+    kind: TransactionKind,
+    amount: Amount,
+    currency: Currency,
+    state: DisputeState,
+}
+
+impl TransactionReport {
+    /// The fee charged by `tx`, credited to the house account, if any.
+    fn fee(tx: &Transaction) -> Option<Self> {
+        tx.fee.map(|fee| Self {
+            tx: tx.id,
+            client: fee.account,
+            counterparty: Some(tx.client),
+            kind: TransactionKind::Fee,
+            amount: fee.amount,
+            currency: tx.currency,
+            state: DisputeState::Settled,
+        })
     }
 }
 
-impl Serialize for Client {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut state = serializer.serialize_struct("Client", 5)?;
-        state.serialize_field("client", &self.id)?;
-        state.serialize_field("available", &self.available)?;
-        state.serialize_field("held", &self.held)?;
-        state.serialize_field("total", &(&self.available + &self.held))?; // WARN: assumes no overflow
-        state.serialize_field("locked", &self.locked)?;
-        state.end()
+impl From<&Transaction> for TransactionReport {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            tx: tx.id,
+            client: tx.client,
+            counterparty: tx.counterparty,
+            kind: tx.kind,
+            amount: tx.amount,
+            currency: tx.currency,
+            state: tx.state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event::{FundsEvent, ReferenceEvent, TransferEvent};
+    use crate::fees::{FeeSchedule, FeeTier};
+    use crate::format::InputFormat;
+    use crate::journal::{self, FileJournal};
+    use crate::logger::NoopLogger;
+    use crate::rejection::CsvRejectionSink;
+    use crate::store::{ConcurrentStore, InMemoryStore};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    /// Generates a reproducible mix of all event types across many clients and a few currencies,
+    /// including events which will be rejected (overdrafts, disputes on other clients'
+    /// transactions, etc.).
+    fn write_generated_input(name: &str, events: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "transaction_processor-{}-{}.csv",
+            std::process::id(),
+            name
+        ));
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "type,client,tx,amount,currency,destination").unwrap();
+
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % bound
+        };
+        let mut tx_clients: Vec<u64> = Vec::new();
+        for _ in 0..events {
+            let client = next(40) + 1;
+            let amount = format!("{}.{:04}", next(100), next(10_000));
+            let currency = ["", "EUR", "USD"][next(3) as usize];
+            match next(10) {
+                0..=4 => {
+                    tx_clients.push(client);
+                    writeln!(
+                        file,
+                        "deposit,{},{},{},{},",
+                        client,
+                        tx_clients.len(),
+                        amount,
+                        currency
+                    )
+                    .unwrap();
+                }
+                5 => {
+                    tx_clients.push(client);
+                    writeln!(
+                        file,
+                        "withdrawal,{},{},{},{},",
+                        client,
+                        tx_clients.len(),
+                        amount,
+                        currency
+                    )
+                    .unwrap();
+                }
+                6 => {
+                    tx_clients.push(client);
+                    writeln!(
+                        file,
+                        "transfer,{},{},{},{},{}",
+                        client,
+                        tx_clients.len(),
+                        amount,
+                        currency,
+                        next(40) + 1
+                    )
+                    .unwrap();
+                }
+                kind if !tx_clients.is_empty() => {
+                    let tx = next(tx_clients.len() as u64) as usize;
+                    // Mostly refer to the transaction's own client, occasionally to another
+                    let client = if next(5) == 0 { client } else { tx_clients[tx] };
+                    let event_type = ["dispute", "resolve", "chargeback"][kind as usize - 7];
+                    writeln!(file, "{},{},{},,,", event_type, client, tx + 1).unwrap();
+                }
+                _ => (),
+            }
+        }
+        path
+    }
+
+    /// Writer whose contents remain readable after it has been handed to a runner.
+    #[derive(Default, Clone)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn write_input(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "transaction_processor-{}-{}.csv",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn sorted_clients<S: Store>(store: &S) -> Vec<Client> {
+        let mut clients = store.dump_clients().unwrap();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+
+    fn sorted_transactions<S: Store>(store: &S) -> Vec<Transaction> {
+        let mut transactions = store.dump_transactions().unwrap();
+        transactions.sort_by_key(|tx| tx.id);
+        transactions
+    }
+
+    /// Charges fees on the generated input, paid to a client which also has events of its own.
+    fn fee_policy() -> Policy {
+        let fees = FeeSchedule::default()
+            .with_tier(
+                EventType::Withdrawal,
+                FeeTier {
+                    flat: "0.1".parse().unwrap(),
+                    ..FeeTier::default()
+                },
+            )
+            .with_tier(
+                EventType::Deposit,
+                FeeTier {
+                    from: "50".parse().unwrap(),
+                    rate: Some("0.01".parse().unwrap()),
+                    ..FeeTier::default()
+                },
+            );
+        Policy::default().with_fees(fees).with_house_account(1)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn sharded_runner_matches_single_process_runner() {
+        let path = write_generated_input("sharded", 20_000);
+        let input_file = path.to_str().unwrap();
+
+        let policy = fee_policy();
+        let single_rejections = Arc::new(Mutex::new(Vec::new()));
+        let mut single = CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(input_file)
+            .with_policy(policy.clone())
+            .with_rejections(Arc::clone(&single_rejections));
+        let single_summary = single.run().await.unwrap();
+        let sharded_rejections = Arc::new(Mutex::new(Vec::new()));
+        let mut sharded = ShardedRunner::<ConcurrentStore, NoopLogger>::new(input_file, 8)
+            .with_policy(policy)
+            .with_rejections(Arc::clone(&sharded_rejections));
+        let sharded_summary = sharded.run().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let expected_clients = sorted_clients(single.store());
+        assert!(expected_clients.iter().any(|client| client.locked));
+        assert_eq!(sorted_clients(sharded.store()), expected_clients);
+        let expected_transactions = sorted_transactions(single.store());
+        assert_eq!(sorted_transactions(sharded.store()), expected_transactions);
+        assert!(single_summary.charged_back.len() > 1);
+        assert!(!single_summary.transferred.is_empty());
+        assert!(!single_summary.fees.is_empty());
+        assert!(expected_transactions
+            .iter()
+            .any(|tx| tx.kind == TransactionKind::Transfer && tx.state != DisputeState::Settled));
+        assert!(expected_clients
+            .iter()
+            .any(|client| client.balances.len() > 1));
+        assert!(single_summary.locked_accounts > 0);
+        assert!(single_summary
+            .rejections
+            .contains_key("client_transaction_mismatch"));
+        assert_eq!(untimed(sharded_summary), untimed(single_summary));
+        // Workers report rejections as they go, so only their order differs
+        let sorted = |rejections: Arc<Mutex<Vec<Rejection>>>| {
+            let mut rejections = rejections.lock().unwrap().clone();
+            rejections.sort_by_key(|rejection| rejection.line);
+            rejections
+        };
+        assert_eq!(sorted(sharded_rejections), sorted(single_rejections));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn sharded_runner_creates_each_transaction_once() {
+        let path = write_input(
+            "sharded-duplicate-tx",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 2, 1, 7.0\n\
+             deposit, 2, 3, 1.0\n\
+             dispute, 2, 1,\n\
+             withdrawal, 3, 2, 1.0\n\
+             deposit, 4, 2, 2.0\n",
+        );
+        let journal_path = std::env::temp_dir().join(format!(
+            "transaction_processor-{}-duplicate-tx-journal.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&journal_path);
+        let rejections = Arc::new(Mutex::new(Vec::new()));
+        let mut sharded =
+            ShardedRunner::<ConcurrentStore, NoopLogger>::new(path.to_str().unwrap(), 4)
+                .with_journal(FileJournal::open(&journal_path).unwrap())
+                .with_rejections(Arc::clone(&rejections));
+        let summary = sharded.run().await.unwrap();
+        let entries = FileJournal::read(&journal_path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&journal_path);
+
+        assert_eq!(summary.applied, 3);
+        let mut codes: Vec<_> = rejections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|rejection| (rejection.line, rejection.code.clone()))
+            .collect();
+        codes.sort();
+        assert_eq!(
+            codes,
+            vec![
+                (Some(3), "transaction_exists".to_owned()),
+                (Some(5), "client_transaction_mismatch".to_owned()),
+                (Some(6), "client_missing".to_owned()),
+            ]
+        );
+        assert_eq!(
+            sharded
+                .store()
+                .get_transaction(1)
+                .unwrap()
+                .map(|tx| tx.client),
+            Some(1)
+        );
+        assert_eq!(
+            sharded
+                .store()
+                .get_client(2)
+                .unwrap()
+                .map(|client| client.balance(Currency::UNSPECIFIED).available),
+            Some(Amount::from_units(10_000))
+        );
+        assert_eq!(
+            sharded
+                .store()
+                .get_transaction(2)
+                .unwrap()
+                .map(|tx| tx.client),
+            Some(4)
+        );
+        let mut replayed = InMemoryStore::default();
+        journal::replay(&entries, &mut replayed).unwrap();
+        assert_eq!(sorted_clients(&replayed), sorted_clients(sharded.store()));
+    }
+
+    /// The summary without its timing, which differs between runs.
+    fn untimed(summary: RunSummary) -> RunSummary {
+        RunSummary {
+            elapsed: Duration::ZERO,
+            rows_per_second: 0.0,
+            ..summary
+        }
+    }
+
+    #[tokio::test]
+    async fn single_process_runner_reports_rejections() {
+        let first = write_input(
+            "rejected-first",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             withdrawal, 1, 2, 5.0\n",
+        );
+        let second = write_input(
+            "rejected-second",
+            "type, client, tx, amount\n\
+             deposit, 1, 3,\n\
+             withdrawal, 1, 4, 0.25\n",
+        );
+        let output = SharedBuffer::default();
+        let rejections = SharedBuffer::default();
+        let mut runner =
+            CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(first.to_str().unwrap())
+                .add_input(second.to_str().unwrap())
+                .with_output(output.clone())
+                .with_rejections(CsvRejectionSink::new(rejections.clone()));
+        let summary = runner.run().await.unwrap();
+        let _ = std::fs::remove_file(&first);
+        let _ = std::fs::remove_file(&second);
+
+        assert_eq!(summary.rows_read, 4);
+        assert_eq!(summary.parse_failures, 1);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(summary.applied, 2);
+        assert_eq!(summary.events.get(&EventType::Withdrawal), Some(&2));
+        assert_eq!(summary.rejections.get("withdrawal_above_balance"), Some(&1));
+        let totals = |units| vec![(Currency::UNSPECIFIED, Amount::from_units(units))];
+        assert_eq!(
+            summary.deposited.into_iter().collect::<Vec<_>>(),
+            totals(10_000)
+        );
+        assert_eq!(
+            summary.withdrawn.into_iter().collect::<Vec<_>>(),
+            totals(2_500)
+        );
+        assert_eq!(
+            output.contents(),
+            "client,available,held,total,locked\n1,0.7500,0.0000,0.7500,false\n"
+        );
+        let report = rejections.contents();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "line,row,client,tx,type,code,message");
+        assert!(lines[1]
+            .starts_with("3,\"withdrawal,1,2,5.0\",1,2,withdrawal,withdrawal_above_balance,"));
+        assert!(lines[2].starts_with("2,\"deposit,1,3,\",1,3,deposit,parse_error,"));
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn single_process_runner_writes_transaction_ledger() {
+        let input = "type, client, tx, amount, destination\n\
+                     deposit, 2, 3, 1.0,\n\
+                     deposit, 1, 1, 2.0,\n\
+                     withdrawal, 1, 2, 0.5,\n\
+                     transfer, 1, 4, 0.25, 2\n\
+                     dispute, 2, 3,,\n";
+        let ledger = SharedBuffer::default();
+        let mut runner = CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(
+            Input::from_reader(input.as_bytes()),
+        )
+        .with_output(io::sink())
+        .with_transaction_output(ledger.clone(), OutputFormat::Csv);
+        runner.run().await.unwrap();
+        assert_eq!(
+            ledger.contents(),
+            "tx,client,counterparty,type,amount,currency,state\n\
+             1,1,,deposit,2.0000,XXX,settled\n\
+             2,1,,withdrawal,-0.5000,XXX,settled\n\
+             3,2,,deposit,1.0000,XXX,disputed\n\
+             4,1,2,transfer,-0.2500,XXX,settled\n"
+        );
+
+        let mut filtered = Vec::new();
+        write_transactions(runner.store(), &mut filtered, OutputFormat::Csv, Some(2)).unwrap();
+        assert_eq!(
+            String::from_utf8(filtered).unwrap(),
+            "tx,client,counterparty,type,amount,currency,state\n\
+             3,2,,deposit,1.0000,XXX,disputed\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn json_lines_input_produces_json_report() {
+        let input = "{\"type\": \"deposit\", \"client\": 2, \"tx\": 1, \"amount\": \"1.5\"}\n\
+                     {\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": 2}\n\
+                     {\"type\": \"dispute\", \"client\": 2, \"tx\": 1}\n\
+                     {\"type\": \"deposit\", \"client\": 1, \"tx\": 3, \"amount\": 0.5, \
+                     \"currency\": \"EUR\"}\n";
+        let output = SharedBuffer::default();
+        let mut runner = CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(
+            Input::from_reader(input.as_bytes()).with_format(InputFormat::JsonLines),
+        )
+        .with_output(output.clone())
+        .with_output_format(OutputFormat::Json);
+        assert!(runner.run().await.unwrap().is_clean());
+        assert_eq!(
+            output.contents(),
+            "[\n\
+             {\"client\":1,\"currency\":\"EUR\",\"available\":\"0.5000\",\"held\":\"0.0000\",\
+             \"total\":\"0.5000\",\"locked\":false},\n\
+             {\"client\":1,\"currency\":\"XXX\",\"available\":\"2.0000\",\"held\":\"0.0000\",\
+             \"total\":\"2.0000\",\"locked\":false},\n\
+             {\"client\":2,\"currency\":\"XXX\",\"available\":\"0.0000\",\"held\":\"1.5000\",\
+             \"total\":\"1.5000\",\"locked\":false}\n\
+             ]\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_report_has_currency_column_only_with_currencies() {
+        let report = |events: Vec<Event>| async move {
+            let mut store = InMemoryStore::default();
+            process_events(events, &mut store, &Policy::default(), &NoopLogger)
+                .await
+                .unwrap();
+            let mut output = Vec::new();
+            write_clients(
+                &store,
+                &mut output,
+                OutputFormat::Csv,
+                ClientOrder::Id,
+                &NoopLogger,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            report(vec![deposit(2, 1, 15_000), deposit(1, 2, 20_000)]).await,
+            "client,available,held,total,locked\n\
+             1,2.0000,0.0000,2.0000,false\n\
+             2,1.5000,0.0000,1.5000,false\n"
+        );
+        let euros = Event::Deposit(FundsEvent {
+            client: 1,
+            tx: 3,
+            amount: Amount::from_units(5_000),
+            currency: "EUR".parse().unwrap(),
+            timestamp: None,
+        });
+        assert_eq!(
+            report(vec![deposit(2, 1, 15_000), deposit(1, 2, 20_000), euros]).await,
+            "client,currency,available,held,total,locked\n\
+             1,EUR,0.5000,0.0000,0.5000,false\n\
+             1,XXX,2.0000,0.0000,2.0000,false\n\
+             2,XXX,1.5000,0.0000,1.5000,false\n"
+        );
+    }
+
+    fn deposit(client: u16, tx: u32, units: i64) -> Event {
+        Event::Deposit(FundsEvent {
+            client,
+            tx,
+            amount: Amount::from_units(units),
+            currency: Currency::UNSPECIFIED,
+            timestamp: None,
+        })
+    }
+
+    fn withdrawal(client: u16, tx: u32, units: i64) -> Event {
+        Event::Withdrawal(FundsEvent {
+            client,
+            tx,
+            amount: Amount::from_units(units),
+            currency: Currency::UNSPECIFIED,
+            timestamp: None,
+        })
+    }
+
+    /// Stream which yields its events one at a time, pending once before each.
+    struct Delayed {
+        events: std::vec::IntoIter<Event>,
+        ready: bool,
+    }
+
+    impl Stream for Delayed {
+        type Item = Event;
+
+        fn poll_next(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Event>> {
+            self.ready = !self.ready;
+            if self.ready {
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            std::task::Poll::Ready(self.events.next())
+        }
+    }
+
+    #[tokio::test]
+    async fn process_events_returns_clients_and_rejections() {
+        let events = vec![
+            deposit(2, 1, 10_000),
+            deposit(1, 2, 20_000),
+            withdrawal(1, 3, 50_000),
+            withdrawal(1, 4, 5_000),
+        ];
+        let mut store = InMemoryStore::default();
+        let outcome = process_events(events.clone(), &mut store, &Policy::default(), &NoopLogger)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome.clients.iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            outcome.clients[0].balance(Currency::UNSPECIFIED).available,
+            Amount::from_units(15_000)
+        );
+        assert_eq!(outcome.summary.rejected, 1);
+        assert_eq!(outcome.rejections.len(), 1);
+        assert_eq!(outcome.rejections[0].tx, Some(3));
+        assert_eq!(outcome.rejections[0].code, "withdrawal_above_balance");
+        assert_eq!(outcome.rejections[0].line, None);
+
+        let stream = Delayed {
+            events: events.into_iter(),
+            ready: false,
+        };
+        let mut store = InMemoryStore::default();
+        let streamed = process_event_stream(stream, &mut store, &Policy::default(), &NoopLogger)
+            .await
+            .unwrap();
+        assert_eq!(streamed.clients, outcome.clients);
+        assert_eq!(streamed.rejections, outcome.rejections);
+        assert_eq!(untimed(streamed.summary), untimed(outcome.summary));
+    }
+
+    /// Applies deposits, withdrawals and a chargeback, with three rejections.
+    async fn summarised_run() -> RunSummary {
+        let reference = |tx| ReferenceEvent {
+            client: 2,
+            tx,
+            timestamp: None,
+        };
+        let events = vec![
+            deposit(1, 1, 10_000),
+            deposit(2, 2, 30_000),
+            withdrawal(1, 3, 4_000),
+            withdrawal(2, 4, 90_000),
+            Event::Dispute(reference(2)),
+            Event::Dispute(reference(99)),
+            Event::Chargeback(reference(2)),
+            withdrawal(2, 5, 1_000),
+        ];
+        let mut store = InMemoryStore::default();
+        let outcome = process_events(events, &mut store, &Policy::default(), &NoopLogger)
+            .await
+            .unwrap();
+        untimed(outcome.summary)
+    }
+
+    #[tokio::test]
+    async fn summary_counts_events_rejections_totals_and_locked_accounts() {
+        let summary = summarised_run().await;
+        assert_eq!(summary.rows_read, 8);
+        assert_eq!(summary.applied, 5);
+        assert_eq!(summary.rejected, 3);
+        assert_eq!(
+            summary.events.into_iter().collect::<Vec<_>>(),
+            vec![
+                (EventType::Deposit, 2),
+                (EventType::Withdrawal, 3),
+                (EventType::Dispute, 2),
+                (EventType::Chargeback, 1),
+            ]
+        );
+        assert_eq!(
+            summary.rejections.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("client_locked", 1),
+                ("transaction_missing", 1),
+                ("withdrawal_above_balance", 1),
+            ]
+        );
+        let totals = |units| vec![(Currency::UNSPECIFIED, Amount::from_units(units))];
+        assert_eq!(
+            summary.deposited.into_iter().collect::<Vec<_>>(),
+            totals(40_000)
+        );
+        assert_eq!(
+            summary.withdrawn.into_iter().collect::<Vec<_>>(),
+            totals(4_000)
+        );
+        assert_eq!(
+            summary.charged_back.into_iter().collect::<Vec<_>>(),
+            totals(30_000)
+        );
+        assert!(summary.transferred.is_empty());
+        assert!(summary.fees.is_empty());
+        assert_eq!(summary.locked_accounts, 1);
+    }
+
+    #[tokio::test]
+    async fn summary_serializes_to_flat_json() {
+        let summary = serde_json::to_value(summarised_run().await).unwrap();
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "rows_read": 8,
+                "parse_failures": 0,
+                "rejected": 3,
+                "applied": 5,
+                "events": {"deposit": 2, "withdrawal": 3, "dispute": 2, "chargeback": 1},
+                "rejections": {
+                    "client_locked": 1,
+                    "transaction_missing": 1,
+                    "withdrawal_above_balance": 1,
+                },
+                "deposited": {"XXX": "4.0000"},
+                "withdrawn": {"XXX": "0.4000"},
+                "transferred": {},
+                "charged_back": {"XXX": "3.0000"},
+                "fees": {},
+                "locked_accounts": 1,
+                "elapsed_seconds": 0.0,
+                "rows_per_second": 0.0,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn transfers_and_their_disputes_change_both_clients() {
+        let transfer = Event::Transfer(TransferEvent {
+            client: 1,
+            tx: 3,
+            destination: 2,
+            amount: Amount::from_units(4_000),
+            currency: Currency::UNSPECIFIED,
+            timestamp: Some(20),
+        });
+        let reference = |tx| ReferenceEvent {
+            client: 1,
+            tx,
+            timestamp: None,
+        };
+        let mut store = InMemoryStore::default();
+        let policy = Policy::default();
+        for event in [deposit(1, 1, 10_000), deposit(2, 2, 1_000), transfer] {
+            process_event(&mut store, &event, &policy, &NoopLogger)
+                .await
+                .unwrap();
+        }
+        let balances = |store: &InMemoryStore| {
+            sorted_clients(store)
+                .iter()
+                .map(|c| c.balance(Currency::UNSPECIFIED))
+                .map(|b| (b.available.units(), b.held.units()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(balances(&store), vec![(6_000, 0), (5_000, 0)]);
+        let destination = store.get_client(2).unwrap().unwrap();
+        assert_eq!(destination.last_event_at, Some(20));
+
+        let dispute = Event::Dispute(reference(3));
+        let entry = process_event(&mut store, &dispute, &policy, &NoopLogger)
+            .await
+            .unwrap();
+        assert!(entry.affects_client(1) && entry.affects_client(2));
+        assert_eq!(balances(&store), vec![(6_000, 0), (1_000, 4_000)]);
+
+        let chargeback = Event::Chargeback(reference(3));
+        process_event(&mut store, &chargeback, &policy, &NoopLogger)
+            .await
+            .unwrap();
+        assert_eq!(balances(&store), vec![(10_000, 0), (1_000, 0)]);
+        assert!(store.get_client(1).unwrap().unwrap().locked);
+    }
+
+    #[tokio::test]
+    async fn fees_are_credited_to_house_account() {
+        let tier = FeeTier {
+            flat: Amount::from_units(100),
+            ..FeeTier::default()
+        };
+        let policy = Policy::default().with_house_account(9).with_fees(
+            FeeSchedule::default()
+                .with_tier(EventType::Deposit, tier)
+                .with_client_tier(2, EventType::Deposit, FeeTier::default()),
+        );
+        let events = vec![deposit(1, 1, 10_000), deposit(2, 2, 10_000)];
+        let mut store = InMemoryStore::default();
+        let outcome = process_events(events, &mut store, &policy, &NoopLogger)
+            .await
+            .unwrap();
+        let balances: Vec<_> = outcome
+            .clients
+            .iter()
+            .map(|c| (c.id, c.balance(Currency::UNSPECIFIED).available.units()))
+            .collect();
+        assert_eq!(balances, vec![(1, 9_900), (2, 10_000), (9, 100)]);
+        assert_eq!(
+            outcome.summary.fees.into_iter().collect::<Vec<_>>(),
+            vec![(Currency::UNSPECIFIED, Amount::from_units(100))]
+        );
+
+        let mut ledger = Vec::new();
+        write_transactions(&store, &mut ledger, OutputFormat::Csv, Some(9)).unwrap();
+        assert_eq!(
+            String::from_utf8(ledger).unwrap(),
+            "tx,client,counterparty,type,amount,currency,state\n\
+             1,9,1,fee,0.0100,XXX,settled\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_carries_state_into_next_run() {
+        let snapshot = SharedBuffer::default();
+        let mut first = CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(
+            Input::from_reader(&b"type, client, tx, amount\ndeposit, 1, 1, 1.0\n"[..]),
+        )
+        .with_snapshot_output(snapshot.clone());
+        first.run().await.unwrap();
+
+        let snapshot = Snapshot::read(snapshot.contents().as_bytes()).unwrap();
+        let mut second = CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(
+            Input::from_reader(&b"type, client, tx, amount\ndispute, 1, 1,\n"[..]),
+        )
+        .with_snapshot(snapshot);
+        assert!(second.run().await.unwrap().is_clean());
+        let client = second.store().get_client(1).unwrap().unwrap();
+        assert_eq!(
+            client.balance(Currency::UNSPECIFIED).held,
+            Amount::from_units(10_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn events_going_back_in_time_are_rejected_per_client() {
+        let path = write_input(
+            "timestamps",
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 1.0, 100\n\
+             deposit, 2, 2, 1.0, 50\n\
+             deposit, 1, 3, 1.0, 90\n\
+             deposit, 1, 4, 1.0,\n\
+             deposit, 1, 5, 1.0, 100\n",
+        );
+        let mut runner =
+            CsvSingleProcessRunner::<InMemoryStore, NoopLogger>::new(path.to_str().unwrap())
+                .with_output(io::sink());
+        let summary = runner.run().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(summary.rejected, 1);
+        let client = runner.store().get_client(1).unwrap().unwrap();
+        assert_eq!(
+            client.balance(Currency::UNSPECIFIED).available,
+            Amount::from_units(30_000)
+        );
+        assert_eq!(client.last_event_at, Some(100));
+        assert_eq!(runner.store().get_transaction(3), Ok(None));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn replaying_journal_rebuilds_sharded_store() {
+        let path = write_generated_input("journal-input", 5_000);
+        let journal_path = std::env::temp_dir().join(format!(
+            "transaction_processor-{}-runner-journal.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&journal_path);
+
+        let mut sharded =
+            ShardedRunner::<ConcurrentStore, NoopLogger>::new(path.to_str().unwrap(), 4)
+                .with_policy(fee_policy())
+                .with_output(io::sink())
+                .with_journal(FileJournal::open(&journal_path).unwrap());
+        sharded.run().await.unwrap();
+        let entries = FileJournal::read(&journal_path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&journal_path);
+
+        let mut replayed = InMemoryStore::default();
+        journal::replay(&entries, &mut replayed).unwrap();
+        assert_eq!(sorted_clients(&replayed), sorted_clients(sharded.store()));
+        assert_eq!(
+            sorted_transactions(&replayed),
+            sorted_transactions(sharded.store())
+        );
     }
 }
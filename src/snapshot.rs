@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::store::{Changeset, Client, Store, StoreError, Transaction};
+
+/// Version of the snapshot format written by this build.
+///
+/// Bump it whenever a change to `Client` or `Transaction` means older builds would misread a
+/// snapshot, or this build would misread an older one.
+pub const SNAPSHOT_VERSION: u32 = 5;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Snapshot could not be accessed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Snapshot is malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("Snapshot has format version {found}, but only version {supported} is supported")]
+    IncompatibleVersion { found: u32, supported: u32 },
+    #[error("Snapshot can only be restored into an empty store")]
+    StoreNotEmpty,
+    #[error("{0}")]
+    StoreError(#[from] StoreError),
+}
+
+/// The full state of a store, so that a later run can continue from it.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Ordered by client ID.
+    pub clients: Vec<Client>,
+    /// Ordered by transaction ID.
+    pub transactions: Vec<Transaction>,
+}
+
+/// Just enough of a snapshot to check its version before parsing the rest.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of every record in `store`.
+    pub fn capture<S: Store>(store: &S) -> Result<Self, StoreError> {
+        let mut clients = store.dump_clients()?;
+        clients.sort_by_key(|client| client.id);
+        let mut transactions = store.dump_transactions()?;
+        transactions.sort_by_key(|tx| tx.id);
+        Ok(Self {
+            version: SNAPSHOT_VERSION,
+            clients,
+            transactions,
+        })
+    }
+
+    /// Writes every record into `store`, which must be empty, in a single changeset.
+    pub fn restore<S: Store>(self, store: &mut S) -> Result<(), SnapshotError> {
+        if !store.dump_clients()?.is_empty() || !store.dump_transactions()?.is_empty() {
+            return Err(SnapshotError::StoreNotEmpty);
+        }
+        store.apply(Changeset {
+            clients: self.clients,
+            transactions: self.transactions,
+            credits: Vec::new(),
+        })?;
+        store.flush()?;
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut output: W) -> Result<(), SnapshotError> {
+        serde_json::to_writer(&mut output, self)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Reads a snapshot, refusing ones written in a different format version.
+    pub fn read<R: Read>(mut input: R) -> Result<Self, SnapshotError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let Header { version } = serde_json::from_slice(&bytes)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::IncompatibleVersion {
+                found: version,
+                supported: SNAPSHOT_VERSION,
+            });
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::currency::Currency;
+    use crate::store::{Balance, DisputeState, InMemoryStore, TransactionKind};
+
+    fn store() -> InMemoryStore {
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut store = InMemoryStore::default();
+        store
+            .apply(Changeset {
+                clients: vec![Client {
+                    id: 1,
+                    balances: [
+                        (
+                            Currency::UNSPECIFIED,
+                            Balance {
+                                available: Amount::from_units(5),
+                                held: Amount::ZERO,
+                            },
+                        ),
+                        (
+                            eur,
+                            Balance {
+                                available: Amount::ZERO,
+                                held: Amount::from_units(2),
+                            },
+                        ),
+                    ]
+                    .iter()
+                    .copied()
+                    .collect(),
+                    locked: false,
+                    last_event_at: Some(10),
+                    ..Client::default()
+                }],
+                transactions: vec![Transaction {
+                    id: 4,
+                    client: 1,
+                    kind: TransactionKind::Deposit,
+                    amount: Amount::from_units(2),
+                    state: DisputeState::Disputed,
+                    currency: eur,
+                    counterparty: None,
+                    fee: None,
+                    created_at: Some(10),
+                }],
+                credits: Vec::new(),
+            })
+            .unwrap();
+        store
+    }
+
+    #[test]
+    fn snapshot_round_trips_store() {
+        let mut output = Vec::new();
+        Snapshot::capture(&store())
+            .unwrap()
+            .write(&mut output)
+            .unwrap();
+        let mut restored = InMemoryStore::default();
+        Snapshot::read(output.as_slice())
+            .unwrap()
+            .restore(&mut restored)
+            .unwrap();
+        assert_eq!(
+            Snapshot::capture(&restored).unwrap(),
+            Snapshot::capture(&store()).unwrap()
+        );
+    }
+
+    #[test]
+    fn read_refuses_other_versions() {
+        let input = br#"{"version": 99, "clients": [], "transactions": []}"#;
+        assert!(matches!(
+            Snapshot::read(&input[..]),
+            Err(SnapshotError::IncompatibleVersion {
+                found: 99,
+                supported: SNAPSHOT_VERSION,
+            })
+        ));
+    }
+
+    #[test]
+    fn restore_refuses_non_empty_store() {
+        let snapshot = Snapshot::capture(&store()).unwrap();
+        assert!(matches!(
+            snapshot.restore(&mut store()),
+            Err(SnapshotError::StoreNotEmpty)
+        ));
+    }
+}
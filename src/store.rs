@@ -1,34 +1,319 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use thiserror::Error;
 
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::event::Timestamp;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
 #[derive(Error, PartialEq, Debug)]
-pub enum StoreError {}
+pub enum StoreError {
+    #[error("Store backend failure: {0}")]
+    Backend(String),
+    #[error("Credit overflows the balance of client {0}")]
+    CreditOverflow(u16),
+}
 
-#[derive(Default, PartialEq, Debug, Clone)]
+/// Where a transaction is in the dispute lifecycle.
+///
+/// A transaction starts `Settled`; a dispute moves it to `Disputed`, which a resolve ends in
+/// `Resolved` and a chargeback in `ChargedBack`. Only `Settled` transactions, and `Resolved` ones
+/// if the policy allows re-disputes, can be disputed.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    #[default]
+    Settled,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+/// The kind of event which created a transaction.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    #[default]
+    Deposit,
+    Withdrawal,
+    Adjustment,
+    Lock,
+    Unlock,
+    /// Records the amount taken from the source currency of a conversion.
+    Conversion,
+    /// Records the amount taken from the source client of a transfer.
+    Transfer,
+    /// A fee credited to the house account; written to the ledger after the transaction which
+    /// charged it, rather than stored.
+    Fee,
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug, Clone)]
 pub struct Transaction {
     pub id: u32,
     pub client: u16,
-    pub amount: f64,
-    pub disputed: bool,
+    pub kind: TransactionKind,
+    /// Signed: negative for withdrawals and debit adjustments.
+    pub amount: Amount,
+    pub state: DisputeState,
+    /// Currency of the amount; disputes move funds in this currency.
+    #[serde(default)]
+    pub currency: Currency,
+    /// The other client whose account the transaction changed: the destination of a transfer.
+    #[serde(default)]
+    pub counterparty: Option<u16>,
+    /// The fee charged on top of the amount, if any.
+    #[serde(default)]
+    pub fee: Option<Fee>,
+    /// Timestamp of the deposit or withdrawal which created the transaction, if it had one.
+    #[serde(default)]
+    pub created_at: Option<Timestamp>,
 }
 
-#[derive(Default, PartialEq, Debug, Clone)]
+impl Transaction {
+    /// The signed amount a dispute holds: what the transaction changed the client's balance by,
+    /// which for a deposit is its amount less any fee, as fees are not refunded.
+    ///
+    /// `None` if the difference overflows an `Amount`.
+    pub fn disputed_amount(&self) -> Option<Amount> {
+        match (self.kind, self.fee) {
+            (TransactionKind::Deposit, Some(fee)) => self.amount.checked_sub(fee.amount),
+            _ => Some(self.amount),
+        }
+    }
+}
+
+/// A fee charged by a transaction, in the transaction's currency.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Fee {
+    /// The house account credited with the fee.
+    pub account: u16,
+    pub amount: Amount,
+}
+
+/// A client's funds in one currency.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+}
+
+const SECONDS_PER_DAY: Timestamp = 24 * 60 * 60;
+
+/// Withdrawals from a balance on one UTC day, counted in days since the Unix epoch.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct DailyTotal {
+    pub day: u64,
+    pub amount: Amount,
+}
+
+impl DailyTotal {
+    /// The total once `amount` is withdrawn at `timestamp`, starting again on a new day.
+    ///
+    /// `None` if the total overflows an `Amount`.
+    pub fn add(
+        previous: Option<DailyTotal>,
+        timestamp: Timestamp,
+        amount: Amount,
+    ) -> Option<DailyTotal> {
+        let day = timestamp / SECONDS_PER_DAY;
+        let so_far = match previous {
+            Some(previous) if previous.day == day => previous.amount,
+            _ => Amount::ZERO,
+        };
+        Some(DailyTotal {
+            day,
+            amount: so_far.checked_add(amount)?,
+        })
+    }
+}
+
+impl Balance {
+    /// `None` if the sum overflows an `Amount`.
+    pub fn total(&self) -> Option<Amount> {
+        self.available.checked_add(self.held)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug, Clone)]
 pub struct Client {
     pub id: u16,
-    pub available: f64,
-    pub held: f64,
+    /// One entry per currency the client has transacted in.
+    pub balances: BTreeMap<Currency, Balance>,
     pub locked: bool,
+    /// Latest timestamp of an event applied to the account; earlier ones are rejected.
+    #[serde(default)]
+    pub last_event_at: Option<Timestamp>,
+    /// Withdrawals on the latest day with any, in each currency with a daily limit.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub withdrawn_today: BTreeMap<Currency, DailyTotal>,
+}
+
+impl Client {
+    /// The client's funds in `currency`, zero if it has never transacted in it.
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    pub fn balance_mut(&mut self, currency: Currency) -> &mut Balance {
+        self.balances.entry(currency).or_default()
+    }
+}
+
+/// Order of the clients returned by `Store::dump_clients_sorted`.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ClientOrder {
+    /// Ascending client ID.
+    #[default]
+    Id,
+    /// Descending total balance summed over all currencies, largest first.
+    Total,
+    /// Locked accounts first.
+    Locked,
+}
+
+impl ClientOrder {
+    /// Sorts `clients`, breaking ties by ascending client ID so the order is fully determined.
+    pub fn sort(self, clients: &mut [Client]) {
+        match self {
+            ClientOrder::Id => clients.sort_by_key(|client| client.id),
+            ClientOrder::Total => clients.sort_by_key(|client| {
+                // Widened so that totals which overflow an `Amount` still sort correctly
+                let total: i128 = client
+                    .balances
+                    .values()
+                    .map(|balance| {
+                        i128::from(balance.available.units()) + i128::from(balance.held.units())
+                    })
+                    .sum();
+                (Reverse(total), client.id)
+            }),
+            ClientOrder::Locked => clients.sort_by_key(|client| (!client.locked, client.id)),
+        }
+    }
+}
+
+/// Funds added to a client's available balance as it is when the credit is applied, rather than
+/// as a whole record, so that concurrent credits to the same account are never lost.
+///
+/// The client is created if it does not exist yet.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Credit {
+    pub client: u16,
+    pub currency: Currency,
+    pub amount: Amount,
+}
+
+/// A unit of work: every record in it is written by `Store::apply`, or none are.
+///
+/// Credits are applied after the records are written.
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct Changeset {
+    pub clients: Vec<Client>,
+    pub transactions: Vec<Transaction>,
+    pub credits: Vec<Credit>,
+}
+
+impl From<(Client, Transaction)> for Changeset {
+    fn from((client, transaction): (Client, Transaction)) -> Self {
+        Self {
+            clients: vec![client],
+            transactions: vec![transaction],
+            credits: Vec::new(),
+        }
+    }
 }
 
 pub trait Store {
-    fn get_client(&self, id: u16) -> Option<Client>;
+    fn get_client(&self, id: u16) -> Result<Option<Client>, StoreError>;
     fn set_client(&mut self, client: Client) -> Result<(), StoreError>;
+    fn remove_client(&mut self, id: u16) -> Result<(), StoreError>;
 
-    fn get_transaction(&self, id: u32) -> Option<Transaction>;
+    fn get_transaction(&self, id: u32) -> Result<Option<Transaction>, StoreError>;
     fn set_transaction(&mut self, transaction: Transaction) -> Result<(), StoreError>;
+    fn remove_transaction(&mut self, id: u32) -> Result<(), StoreError>;
+
+    /// All clients, in no particular order.
+    fn dump_clients(&self) -> Result<Vec<Client>, StoreError>;
+    fn dump_transactions(&self) -> Result<Vec<Transaction>, StoreError>;
+
+    /// All clients in `order`, which must be the same for every store holding the same clients.
+    fn dump_clients_sorted(&self, order: ClientOrder) -> Result<Vec<Client>, StoreError> {
+        let mut clients = self.dump_clients()?;
+        order.sort(&mut clients);
+        Ok(clients)
+    }
+
+    /// Writes all records in `changes` atomically.
+    ///
+    /// The default implementation keeps an undo log of the records it overwrites and restores
+    /// them if any write fails. Stores with native transaction support should override this.
+    fn apply(&mut self, changes: Changeset) -> Result<(), StoreError> {
+        let previous_transactions = changes
+            .transactions
+            .iter()
+            .map(|tx| Ok((tx.id, self.get_transaction(tx.id)?)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        let previous_clients = changes
+            .clients
+            .iter()
+            .map(|client| client.id)
+            .chain(changes.credits.iter().map(|credit| credit.client))
+            .map(|id| Ok((id, self.get_client(id)?)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let result = write_changeset(self, changes);
+        if result.is_err() {
+            // Best effort: the original error is more useful to the caller than a restore failure.
+            for (id, previous) in previous_transactions.into_iter().rev() {
+                let _ = match previous {
+                    Some(tx) => self.set_transaction(tx),
+                    None => self.remove_transaction(id),
+                };
+            }
+            for (id, previous) in previous_clients.into_iter().rev() {
+                let _ = match previous {
+                    Some(client) => self.set_client(client),
+                    None => self.remove_client(id),
+                };
+            }
+        }
+        result
+    }
 
-    fn dump_clients(&self) -> Vec<Client>;
-    fn dump_transactions(&self) -> Vec<Transaction>;
+    /// Persists any writes buffered by the store. Called by runners once all events are processed.
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+fn write_changeset<S: Store + ?Sized>(store: &mut S, changes: Changeset) -> Result<(), StoreError> {
+    for transaction in changes.transactions {
+        store.set_transaction(transaction)?;
+    }
+    for client in changes.clients {
+        store.set_client(client)?;
+    }
+    for credit in changes.credits {
+        let mut client = store.get_client(credit.client)?.unwrap_or(Client {
+            id: credit.client,
+            ..Client::default()
+        });
+        let balance = client.balance_mut(credit.currency);
+        balance.available = balance
+            .available
+            .checked_add(credit.amount)
+            .ok_or(StoreError::CreditOverflow(credit.client))?;
+        store.set_client(client)?;
+    }
+    Ok(())
 }
 
 #[derive(Default)]
@@ -38,8 +323,8 @@ pub struct InMemoryStore {
 }
 
 impl Store for InMemoryStore {
-    fn get_client(&self, id: u16) -> Option<Client> {
-        self.clients.get(&id).cloned()
+    fn get_client(&self, id: u16) -> Result<Option<Client>, StoreError> {
+        Ok(self.clients.get(&id).cloned())
     }
 
     fn set_client(&mut self, client: Client) -> Result<(), StoreError> {
@@ -47,8 +332,13 @@ impl Store for InMemoryStore {
         Ok(())
     }
 
-    fn get_transaction(&self, id: u32) -> Option<Transaction> {
-        self.transactions.get(&id).cloned()
+    fn remove_client(&mut self, id: u16) -> Result<(), StoreError> {
+        self.clients.remove(&id);
+        Ok(())
+    }
+
+    fn get_transaction(&self, id: u32) -> Result<Option<Transaction>, StoreError> {
+        Ok(self.transactions.get(&id).cloned())
     }
 
     fn set_transaction(&mut self, transaction: Transaction) -> Result<(), StoreError> {
@@ -56,11 +346,257 @@ impl Store for InMemoryStore {
         Ok(())
     }
 
-    fn dump_clients(&self) -> Vec<Client> {
-        self.clients.values().map(|client| client.clone()).collect()
+    fn remove_transaction(&mut self, id: u32) -> Result<(), StoreError> {
+        self.transactions.remove(&id);
+        Ok(())
+    }
+
+    fn dump_clients(&self) -> Result<Vec<Client>, StoreError> {
+        Ok(self.clients.values().cloned().collect())
+    }
+
+    fn dump_transactions(&self) -> Result<Vec<Transaction>, StoreError> {
+        Ok(self.transactions.values().cloned().collect())
+    }
+}
+
+/// Thread-safe handle to a shared `InMemoryStore`.
+///
+/// Clones refer to the same underlying store, so one handle can be given to each concurrent
+/// worker. `apply` holds the write lock for the whole changeset, keeping it atomic across handles.
+#[derive(Default, Clone)]
+pub struct ConcurrentStore {
+    inner: Arc<RwLock<InMemoryStore>>,
+}
+
+impl ConcurrentStore {
+    fn read(&self) -> Result<RwLockReadGuard<'_, InMemoryStore>, StoreError> {
+        self.inner
+            .read()
+            .map_err(|_| StoreError::Backend("Store lock poisoned".to_owned()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, InMemoryStore>, StoreError> {
+        self.inner
+            .write()
+            .map_err(|_| StoreError::Backend("Store lock poisoned".to_owned()))
+    }
+}
+
+impl Store for ConcurrentStore {
+    fn get_client(&self, id: u16) -> Result<Option<Client>, StoreError> {
+        self.read()?.get_client(id)
+    }
+
+    fn set_client(&mut self, client: Client) -> Result<(), StoreError> {
+        self.write()?.set_client(client)
+    }
+
+    fn remove_client(&mut self, id: u16) -> Result<(), StoreError> {
+        self.write()?.remove_client(id)
+    }
+
+    fn get_transaction(&self, id: u32) -> Result<Option<Transaction>, StoreError> {
+        self.read()?.get_transaction(id)
+    }
+
+    fn set_transaction(&mut self, transaction: Transaction) -> Result<(), StoreError> {
+        self.write()?.set_transaction(transaction)
+    }
+
+    fn remove_transaction(&mut self, id: u32) -> Result<(), StoreError> {
+        self.write()?.remove_transaction(id)
+    }
+
+    fn dump_clients(&self) -> Result<Vec<Client>, StoreError> {
+        self.read()?.dump_clients()
+    }
+
+    fn dump_transactions(&self) -> Result<Vec<Transaction>, StoreError> {
+        self.read()?.dump_transactions()
+    }
+
+    fn apply(&mut self, changes: Changeset) -> Result<(), StoreError> {
+        self.write()?.apply(changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Wraps an `InMemoryStore`, failing every client write while `fail_client_writes` is set.
+    #[derive(Default)]
+    struct FlakyStore {
+        inner: InMemoryStore,
+        fail_client_writes: bool,
+    }
+
+    impl Store for FlakyStore {
+        fn get_client(&self, id: u16) -> Result<Option<Client>, StoreError> {
+            self.inner.get_client(id)
+        }
+
+        fn set_client(&mut self, client: Client) -> Result<(), StoreError> {
+            if self.fail_client_writes {
+                return Err(StoreError::Backend("injected failure".to_owned()));
+            }
+            self.inner.set_client(client)
+        }
+
+        fn remove_client(&mut self, id: u16) -> Result<(), StoreError> {
+            self.inner.remove_client(id)
+        }
+
+        fn get_transaction(&self, id: u32) -> Result<Option<Transaction>, StoreError> {
+            self.inner.get_transaction(id)
+        }
+
+        fn set_transaction(&mut self, transaction: Transaction) -> Result<(), StoreError> {
+            self.inner.set_transaction(transaction)
+        }
+
+        fn remove_transaction(&mut self, id: u32) -> Result<(), StoreError> {
+            self.inner.remove_transaction(id)
+        }
+
+        fn dump_clients(&self) -> Result<Vec<Client>, StoreError> {
+            self.inner.dump_clients()
+        }
+
+        fn dump_transactions(&self) -> Result<Vec<Transaction>, StoreError> {
+            self.inner.dump_transactions()
+        }
+    }
+
+    fn client(id: u16, available: i64) -> Client {
+        let mut client = Client {
+            id,
+            ..Client::default()
+        };
+        client.balance_mut(Currency::UNSPECIFIED).available = Amount::from_units(available);
+        client
+    }
+
+    fn transaction(id: u32, client: u16, amount: i64, state: DisputeState) -> Transaction {
+        Transaction {
+            id,
+            client,
+            kind: TransactionKind::Deposit,
+            amount: Amount::from_units(amount),
+            state,
+            currency: Currency::UNSPECIFIED,
+            counterparty: None,
+            fee: None,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn apply_writes_client_and_transaction() {
+        let mut store = InMemoryStore::default();
+        let changes = Changeset::from((client(1, 5), transaction(1, 1, 5, DisputeState::Settled)));
+        assert_eq!(store.apply(changes), Ok(()));
+        assert_eq!(store.get_client(1), Ok(Some(client(1, 5))));
+        assert_eq!(
+            store.get_transaction(1),
+            Ok(Some(transaction(1, 1, 5, DisputeState::Settled)))
+        );
+    }
+
+    #[test]
+    fn apply_adds_credits_to_current_balance() {
+        let mut store = InMemoryStore::default();
+        let credit = |client| Credit {
+            client,
+            currency: Currency::UNSPECIFIED,
+            amount: Amount::from_units(2),
+        };
+        let changes = Changeset {
+            credits: vec![credit(1), credit(9)],
+            ..Changeset::from((client(1, 5), transaction(1, 1, 5, DisputeState::Settled)))
+        };
+        assert_eq!(store.apply(changes.clone()), Ok(()));
+        assert_eq!(store.get_client(1), Ok(Some(client(1, 7))));
+        assert_eq!(store.get_client(9), Ok(Some(client(9, 2))));
+        assert_eq!(store.apply(changes), Ok(()));
+        assert_eq!(store.get_client(9), Ok(Some(client(9, 4))));
+    }
+
+    #[test]
+    fn apply_rolls_back_new_transaction_on_failure() {
+        let mut store = FlakyStore {
+            fail_client_writes: true,
+            ..FlakyStore::default()
+        };
+        let changes = Changeset::from((client(1, 5), transaction(1, 1, 5, DisputeState::Settled)));
+        assert!(store.apply(changes).is_err());
+        assert_eq!(store.get_client(1), Ok(None));
+        assert_eq!(store.get_transaction(1), Ok(None));
+    }
+
+    #[test]
+    fn apply_restores_previous_transaction_on_failure() {
+        let mut store = FlakyStore::default();
+        let initial = Changeset::from((client(1, 5), transaction(1, 1, 5, DisputeState::Settled)));
+        assert_eq!(store.apply(initial), Ok(()));
+
+        store.fail_client_writes = true;
+        let dispute = Changeset::from((client(1, 0), transaction(1, 1, 5, DisputeState::Disputed)));
+        assert!(store.apply(dispute).is_err());
+        assert_eq!(store.get_client(1), Ok(Some(client(1, 5))));
+        assert_eq!(
+            store.get_transaction(1),
+            Ok(Some(transaction(1, 1, 5, DisputeState::Settled)))
+        );
+    }
+
+    #[test]
+    fn dump_clients_sorted_breaks_ties_by_id() {
+        let mut store = InMemoryStore::default();
+        for (id, available, held, locked) in [(3, 5, 0, true), (1, 2, 3, true), (2, 9, 0, false)] {
+            let mut client = Client {
+                locked,
+                ..client(id, available)
+            };
+            client.balance_mut(Currency::UNSPECIFIED).held = Amount::from_units(held);
+            store.set_client(client).unwrap();
+        }
+        let ids = |order| -> Vec<u16> {
+            let clients = store.dump_clients_sorted(order).unwrap();
+            clients.iter().map(|client| client.id).collect()
+        };
+        assert_eq!(ids(ClientOrder::Id), vec![1, 2, 3]);
+        assert_eq!(ids(ClientOrder::Total), vec![2, 1, 3]);
+        assert_eq!(ids(ClientOrder::Locked), vec![1, 3, 2]);
     }
 
-    fn dump_transactions(&self) -> Vec<Transaction> {
-        self.transactions.values().map(|tx| tx.clone()).collect()
+    #[test]
+    fn daily_total_starts_again_each_day() {
+        let units = Amount::from_units;
+        let first = DailyTotal::add(None, SECONDS_PER_DAY * 3 + 5, units(2)).unwrap();
+        assert_eq!(
+            first,
+            DailyTotal {
+                day: 3,
+                amount: units(2)
+            }
+        );
+        let same_day = DailyTotal::add(Some(first), SECONDS_PER_DAY * 4 - 1, units(4)).unwrap();
+        assert_eq!(
+            same_day,
+            DailyTotal {
+                day: 3,
+                amount: units(6)
+            }
+        );
+        let next_day = DailyTotal::add(Some(same_day), SECONDS_PER_DAY * 4, units(1));
+        assert_eq!(
+            next_day,
+            Some(DailyTotal {
+                day: 4,
+                amount: units(1)
+            })
+        );
     }
 }
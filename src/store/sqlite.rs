@@ -0,0 +1,644 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::path::Path;
+
+use super::{
+    write_changeset, Balance, Changeset, Client, DailyTotal, DisputeState, Fee, Store, StoreError,
+    Transaction, TransactionKind,
+};
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::event::Timestamp;
+
+const SCHEMA_VERSION: i64 = 8;
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        id INTEGER PRIMARY KEY,
+        locked INTEGER NOT NULL,
+        last_event_at INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS balances (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS daily_withdrawals (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        day INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        created_at INTEGER,
+        kind TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        counterparty INTEGER,
+        fee_account INTEGER,
+        fee_amount INTEGER
+    );
+";
+
+/// `MIGRATIONS[i]` upgrades a database from schema version `i + 1` to `i + 2`.
+const MIGRATIONS: [&str; 7] = [
+    // Timestamps
+    "ALTER TABLE clients ADD COLUMN last_event_at INTEGER;
+     ALTER TABLE transactions ADD COLUMN created_at INTEGER;",
+    // Dispute lifecycle state, replacing the disputed flag
+    "ALTER TABLE transactions ADD COLUMN state TEXT NOT NULL DEFAULT 'settled';
+     UPDATE transactions SET state = 'disputed' WHERE disputed = 1;
+     ALTER TABLE transactions DROP COLUMN disputed;",
+    // Transaction kind, which was implied by the sign of the amount
+    "ALTER TABLE transactions ADD COLUMN kind TEXT NOT NULL DEFAULT 'deposit';
+     UPDATE transactions SET kind = 'withdrawal' WHERE amount < 0;",
+    // Per-currency balances; existing records have no currency
+    "CREATE TABLE balances (
+         client INTEGER NOT NULL,
+         currency TEXT NOT NULL,
+         available INTEGER NOT NULL,
+         held INTEGER NOT NULL,
+         PRIMARY KEY (client, currency)
+     );
+     INSERT INTO balances SELECT id, 'XXX', available, held FROM clients;
+     ALTER TABLE clients DROP COLUMN available;
+     ALTER TABLE clients DROP COLUMN held;
+     ALTER TABLE transactions ADD COLUMN currency TEXT NOT NULL DEFAULT 'XXX';",
+    // Transfer destinations
+    "ALTER TABLE transactions ADD COLUMN counterparty INTEGER;",
+    // Fees
+    "ALTER TABLE transactions ADD COLUMN fee_account INTEGER;
+     ALTER TABLE transactions ADD COLUMN fee_amount INTEGER;",
+    // Daily withdrawal totals
+    "CREATE TABLE daily_withdrawals (
+         client INTEGER NOT NULL,
+         currency TEXT NOT NULL,
+         day INTEGER NOT NULL,
+         amount INTEGER NOT NULL,
+         PRIMARY KEY (client, currency)
+     );",
+];
+
+/// Stores a fieldless enum as a TEXT column, using the same names as its serde representation.
+macro_rules! text_column {
+    ($type:ident { $($variant:ident => $text:literal),* $(,)? }) => {
+        impl ToSql for $type {
+            fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+                Ok(ToSqlOutput::from(match self {
+                    $($type::$variant => $text,)*
+                }))
+            }
+        }
+
+        impl FromSql for $type {
+            fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+                match value.as_str()? {
+                    $($text => Ok($type::$variant),)*
+                    other => Err(FromSqlError::Other(
+                        format!("Unknown {} {:?}", stringify!($type), other).into(),
+                    )),
+                }
+            }
+        }
+    };
+}
+
+text_column!(DisputeState {
+    Settled => "settled",
+    Disputed => "disputed",
+    Resolved => "resolved",
+    ChargedBack => "charged_back",
+});
+
+text_column!(TransactionKind {
+    Deposit => "deposit",
+    Withdrawal => "withdrawal",
+    Adjustment => "adjustment",
+    Lock => "lock",
+    Unlock => "unlock",
+    Conversion => "conversion",
+    Transfer => "transfer",
+    Fee => "fee",
+});
+
+impl ToSql for Currency {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Currency {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+/// Store backed by an SQLite database file.
+///
+/// Writes are grouped into database transactions of `batch_size` changesets, which are committed
+/// once the batch is full or on `flush`. Opening an existing file resumes from its balances.
+pub struct SqliteStore {
+    conn: Connection,
+    batch_size: usize,
+    pending: usize,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, StoreError> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        match version {
+            0 => {
+                conn.execute_batch(SCHEMA)?;
+                conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            }
+            SCHEMA_VERSION => (),
+            _ if (1..SCHEMA_VERSION).contains(&version) => {
+                conn.execute_batch("BEGIN")?;
+                for migration in &MIGRATIONS[version as usize - 1..] {
+                    conn.execute_batch(migration)?;
+                }
+                conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+                conn.execute_batch("COMMIT")?;
+            }
+            _ => {
+                return Err(StoreError::Backend(format!(
+                    "Unsupported database schema version {}",
+                    version
+                )))
+            }
+        }
+        Ok(Self {
+            conn,
+            batch_size: DEFAULT_BATCH_SIZE,
+            pending: 0,
+        })
+    }
+}
+
+/// Reads a client without its balances and daily totals, which are kept in their own tables.
+fn client_from_row(row: &Row) -> rusqlite::Result<Client> {
+    Ok(Client {
+        id: row.get(0)?,
+        balances: BTreeMap::new(),
+        locked: row.get(1)?,
+        last_event_at: timestamp_from_row(row, 2)?,
+        withdrawn_today: BTreeMap::new(),
+    })
+}
+
+/// Reads a `(client, currency, balance)` row of the balances table.
+fn balance_from_row(row: &Row) -> rusqlite::Result<(u16, Currency, Balance)> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        Balance {
+            available: Amount::from_units(row.get(2)?),
+            held: Amount::from_units(row.get(3)?),
+        },
+    ))
+}
+
+/// Reads a `(client, currency, total)` row of the daily withdrawals table.
+fn daily_total_from_row(row: &Row) -> rusqlite::Result<(u16, Currency, DailyTotal)> {
+    let day: i64 = row.get(2)?;
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        DailyTotal {
+            day: u64::try_from(day)
+                .map_err(|_| rusqlite::Error::IntegralValueOutOfRange(2, day))?,
+            amount: Amount::from_units(row.get(3)?),
+        },
+    ))
+}
+
+fn transaction_from_row(row: &Row) -> rusqlite::Result<Transaction> {
+    Ok(Transaction {
+        id: row.get(0)?,
+        client: row.get(1)?,
+        kind: row.get(5)?,
+        amount: Amount::from_units(row.get(2)?),
+        state: row.get(3)?,
+        currency: row.get(6)?,
+        counterparty: row.get(7)?,
+        fee: fee_from_row(row, 8)?,
+        created_at: timestamp_from_row(row, 4)?,
+    })
+}
+
+/// Reads a fee stored as its house account and amount columns, both null if there was none.
+fn fee_from_row(row: &Row, index: usize) -> rusqlite::Result<Option<Fee>> {
+    let account: Option<u16> = row.get(index)?;
+    let amount: Option<i64> = row.get(index + 1)?;
+    Ok(account.zip(amount).map(|(account, units)| Fee {
+        account,
+        amount: Amount::from_units(units),
+    }))
+}
+
+/// SQLite integers are signed, so timestamps are stored as `i64`.
+fn timestamp_from_row(row: &Row, index: usize) -> rusqlite::Result<Option<Timestamp>> {
+    row.get::<_, Option<i64>>(index)?
+        .map(|timestamp| {
+            Timestamp::try_from(timestamp)
+                .map_err(|_| rusqlite::Error::IntegralValueOutOfRange(index, timestamp))
+        })
+        .transpose()
+}
+
+fn timestamp_to_sql(timestamp: Option<Timestamp>) -> Result<Option<i64>, StoreError> {
+    timestamp
+        .map(|timestamp| {
+            i64::try_from(timestamp).map_err(|_| {
+                StoreError::Backend(format!("Timestamp {} is out of range", timestamp))
+            })
+        })
+        .transpose()
+}
+
+impl Store for SqliteStore {
+    fn get_client(&self, id: u16) -> Result<Option<Client>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, locked, last_event_at FROM clients WHERE id = ?1")?;
+        let mut client = match stmt.query_row([id], client_from_row).optional()? {
+            None => return Ok(None),
+            Some(client) => client,
+        };
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT client, currency, available, held FROM balances WHERE client = ?1",
+        )?;
+        for row in stmt.query_map([id], balance_from_row)? {
+            let (_, currency, balance) = row?;
+            client.balances.insert(currency, balance);
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT client, currency, day, amount FROM daily_withdrawals WHERE client = ?1",
+        )?;
+        for row in stmt.query_map([id], daily_total_from_row)? {
+            let (_, currency, total) = row?;
+            client.withdrawn_today.insert(currency, total);
+        }
+        Ok(Some(client))
+    }
+
+    fn set_client(&mut self, client: Client) -> Result<(), StoreError> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO clients (id, locked, last_event_at) VALUES (?1, ?2, ?3)",
+        )?;
+        stmt.execute(params![
+            client.id,
+            client.locked,
+            timestamp_to_sql(client.last_event_at)?
+        ])?;
+        self.conn
+            .prepare_cached("DELETE FROM balances WHERE client = ?1")?
+            .execute([client.id])?;
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO balances (client, currency, available, held) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (currency, balance) in &client.balances {
+            stmt.execute(params![
+                client.id,
+                currency,
+                balance.available.units(),
+                balance.held.units()
+            ])?;
+        }
+        self.conn
+            .prepare_cached("DELETE FROM daily_withdrawals WHERE client = ?1")?
+            .execute([client.id])?;
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO daily_withdrawals (client, currency, day, amount) \
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (currency, total) in &client.withdrawn_today {
+            let day = i64::try_from(total.day)
+                .map_err(|_| StoreError::Backend(format!("Day {} is out of range", total.day)))?;
+            stmt.execute(params![client.id, currency, day, total.amount.units()])?;
+        }
+        Ok(())
+    }
+
+    fn remove_client(&mut self, id: u16) -> Result<(), StoreError> {
+        self.conn
+            .prepare_cached("DELETE FROM balances WHERE client = ?1")?
+            .execute([id])?;
+        self.conn
+            .prepare_cached("DELETE FROM daily_withdrawals WHERE client = ?1")?
+            .execute([id])?;
+        self.conn
+            .prepare_cached("DELETE FROM clients WHERE id = ?1")?
+            .execute([id])?;
+        Ok(())
+    }
+
+    fn get_transaction(&self, id: u32) -> Result<Option<Transaction>, StoreError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, client, amount, state, created_at, kind, currency, counterparty, \
+             fee_account, fee_amount FROM transactions WHERE id = ?1",
+        )?;
+        Ok(stmt.query_row([id], transaction_from_row).optional()?)
+    }
+
+    fn set_transaction(&mut self, transaction: Transaction) -> Result<(), StoreError> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO transactions \
+             (id, client, amount, state, created_at, kind, currency, counterparty, \
+             fee_account, fee_amount) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        stmt.execute(params![
+            transaction.id,
+            transaction.client,
+            transaction.amount.units(),
+            transaction.state,
+            timestamp_to_sql(transaction.created_at)?,
+            transaction.kind,
+            transaction.currency,
+            transaction.counterparty,
+            transaction.fee.map(|fee| fee.account),
+            transaction.fee.map(|fee| fee.amount.units())
+        ])?;
+        Ok(())
+    }
+
+    fn remove_transaction(&mut self, id: u32) -> Result<(), StoreError> {
+        self.conn
+            .prepare_cached("DELETE FROM transactions WHERE id = ?1")?
+            .execute([id])?;
+        Ok(())
+    }
+
+    fn dump_clients(&self) -> Result<Vec<Client>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, locked, last_event_at FROM clients")?;
+        let mut clients = stmt
+            .query_map([], client_from_row)?
+            .map(|client| client.map(|client| (client.id, client)))
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT client, currency, available, held FROM balances")?;
+        for row in stmt.query_map([], balance_from_row)? {
+            let (id, currency, balance) = row?;
+            if let Some(client) = clients.get_mut(&id) {
+                client.balances.insert(currency, balance);
+            }
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT client, currency, day, amount FROM daily_withdrawals")?;
+        for row in stmt.query_map([], daily_total_from_row)? {
+            let (id, currency, total) = row?;
+            if let Some(client) = clients.get_mut(&id) {
+                client.withdrawn_today.insert(currency, total);
+            }
+        }
+        Ok(clients.into_values().collect())
+    }
+
+    fn dump_transactions(&self) -> Result<Vec<Transaction>, StoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, client, amount, state, created_at, kind, currency, counterparty, \
+             fee_account, fee_amount FROM transactions",
+        )?;
+        let transactions = stmt
+            .query_map([], transaction_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(transactions)
+    }
+
+    fn apply(&mut self, changes: Changeset) -> Result<(), StoreError> {
+        if self.conn.is_autocommit() {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        self.conn.execute_batch("SAVEPOINT apply")?;
+        if let Err(e) = write_changeset(self, changes) {
+            self.conn
+                .execute_batch("ROLLBACK TO apply; RELEASE apply")?;
+            return Err(e);
+        }
+        self.conn.execute_batch("RELEASE apply")?;
+
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT")?;
+        }
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteStore {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn client(id: u16, available: i64) -> Client {
+        let mut client = Client {
+            id,
+            ..Client::default()
+        };
+        client.balance_mut(Currency::UNSPECIFIED).available = Amount::from_units(available);
+        client
+    }
+
+    fn transaction(id: u32, client: u16, amount: i64) -> Transaction {
+        Transaction {
+            id,
+            client,
+            kind: TransactionKind::Deposit,
+            amount: Amount::from_units(amount),
+            state: DisputeState::Settled,
+            currency: Currency::UNSPECIFIED,
+            counterparty: None,
+            fee: None,
+            created_at: Some(1_700_000_000),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "transaction_processor-{}-{}.db",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn apply_round_trips_records() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let changes = Changeset::from((client(1, 5), transaction(1, 1, 5)));
+        assert_eq!(store.apply(changes), Ok(()));
+        assert_eq!(store.get_client(1), Ok(Some(client(1, 5))));
+        assert_eq!(store.get_transaction(1), Ok(Some(transaction(1, 1, 5))));
+        assert_eq!(store.get_client(2), Ok(None));
+    }
+
+    #[test]
+    fn clients_keep_balances_per_currency() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut multi = client(1, 5);
+        multi.balance_mut(eur).held = Amount::from_units(7);
+        multi.withdrawn_today.insert(
+            eur,
+            DailyTotal {
+                day: 19_000,
+                amount: Amount::from_units(3),
+            },
+        );
+        let deposit = Transaction {
+            currency: eur,
+            fee: Some(Fee {
+                account: 9,
+                amount: Amount::from_units(1),
+            }),
+            ..transaction(1, 1, 7)
+        };
+        assert_eq!(
+            store.apply(Changeset::from((multi.clone(), deposit.clone()))),
+            Ok(())
+        );
+        assert_eq!(store.get_client(1), Ok(Some(multi.clone())));
+        assert_eq!(store.get_transaction(1), Ok(Some(deposit)));
+        let transfer = Transaction {
+            kind: TransactionKind::Transfer,
+            counterparty: Some(2),
+            ..transaction(2, 1, -3)
+        };
+        assert_eq!(store.set_transaction(transfer.clone()), Ok(()));
+        assert_eq!(store.get_transaction(2), Ok(Some(transfer)));
+
+        multi.balances.remove(&eur);
+        assert_eq!(store.set_client(multi.clone()), Ok(()));
+        assert_eq!(store.dump_clients(), Ok(vec![multi]));
+    }
+
+    #[test]
+    fn apply_rolls_back_on_failure() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store
+            .conn
+            .execute_batch(
+                "CREATE TRIGGER fail BEFORE INSERT ON clients WHEN NEW.id = 2
+                 BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+            )
+            .unwrap();
+        assert_eq!(
+            store.apply(Changeset::from((client(1, 5), transaction(1, 1, 5)))),
+            Ok(())
+        );
+        let failing = Changeset::from((client(2, 5), transaction(2, 2, 5)));
+        assert!(store.apply(failing).is_err());
+        assert_eq!(store.flush(), Ok(()));
+        assert_eq!(store.get_transaction(2), Ok(None));
+        assert_eq!(store.get_transaction(1), Ok(Some(transaction(1, 1, 5))));
+    }
+
+    #[test]
+    fn reopening_database_resumes_balances() {
+        let path = temp_path("resume");
+        {
+            let mut store = SqliteStore::open(&path).unwrap().with_batch_size(10);
+            let changes = Changeset::from((client(1, 5), transaction(1, 1, 5)));
+            assert_eq!(store.apply(changes), Ok(()));
+        }
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.dump_clients(), Ok(vec![client(1, 5)]));
+        assert_eq!(store.dump_transactions(), Ok(vec![transaction(1, 1, 5)]));
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn opening_version_1_database_migrates_it() {
+        let path = temp_path("migrate");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE clients (id INTEGER PRIMARY KEY, available INTEGER NOT NULL,
+                     held INTEGER NOT NULL, locked INTEGER NOT NULL);
+                 CREATE TABLE transactions (id INTEGER PRIMARY KEY, client INTEGER NOT NULL,
+                     amount INTEGER NOT NULL, disputed INTEGER NOT NULL);
+                 INSERT INTO clients VALUES (1, 6, 0, 0);
+                 INSERT INTO transactions VALUES (1, 1, 5, 0), (2, 1, 3, 1), (4, 1, -2, 0);
+                 PRAGMA user_version = 1;",
+            )
+            .unwrap();
+        }
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.get_client(1), Ok(Some(client(1, 6))));
+        let migrated = Transaction {
+            created_at: None,
+            ..transaction(1, 1, 5)
+        };
+        assert_eq!(store.get_transaction(1), Ok(Some(migrated)));
+        let disputed = Transaction {
+            state: DisputeState::Disputed,
+            created_at: None,
+            ..transaction(2, 1, 3)
+        };
+        assert_eq!(store.get_transaction(2), Ok(Some(disputed)));
+        let withdrawal = Transaction {
+            kind: TransactionKind::Withdrawal,
+            created_at: None,
+            ..transaction(4, 1, -2)
+        };
+        assert_eq!(store.get_transaction(4), Ok(Some(withdrawal)));
+        assert_eq!(
+            store.apply(Changeset::from((client(3, 5), transaction(3, 3, 5)))),
+            Ok(())
+        );
+        assert_eq!(store.get_transaction(3), Ok(Some(transaction(3, 3, 5))));
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+}